@@ -0,0 +1,111 @@
+//! Smooth transitions between view rotations.
+//!
+//! A [`Transition`] remembers the orientation it started from and slerps towards whatever the
+//! target is at the moment, so the target may keep changing (e.g. while dragging) mid-flight.
+
+use cgmath::{Matrix3, Quaternion};
+
+pub struct Transition {
+    from: Matrix3<f32>,
+    /// `from` for the slerp.  Either sign will do, the slerp takes the short way round.
+    from_quaternion: Quaternion<f32>,
+    /// seconds, on the clock of `egui::InputState::time`
+    start: f64,
+    /// seconds
+    duration: f32,
+}
+
+impl Transition {
+    pub fn new(from: Matrix3<f32>, start: f64, duration: f32) -> Self {
+        Self {
+            from,
+            from_quaternion: Quaternion::from(from),
+            start,
+            duration,
+        }
+    }
+
+    /// how far along the transition is at time `now`, from 0 to 1
+    pub fn progress(&self, now: f64) -> f32 {
+        if self.duration <= 0.0 {
+            return 1.0;
+        }
+        (((now - self.start) / self.duration as f64) as f32).clamp(0.0, 1.0)
+    }
+
+    pub fn finished(&self, now: f64) -> bool {
+        self.progress(now) >= 1.0
+    }
+
+    /// the rotation to show at time `now`, on the way to `target`
+    pub fn matrix(&self, target: Matrix3<f32>, now: f64) -> Matrix3<f32> {
+        let t = self.progress(now);
+        // ease in and out, so the motion starts and stops gently
+        let eased = t * t * (3.0 - 2.0 * t);
+        // exactly at the ends, without the rounding of the quaternions
+        if eased <= 0.0 {
+            return self.from;
+        }
+        if eased >= 1.0 {
+            return target;
+        }
+        Matrix3::from(self.from_quaternion.slerp(Quaternion::from(target), eased))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{Deg, InnerSpace, Vector3};
+
+    fn rotation(axis: Vector3<f32>, degrees: f32) -> Matrix3<f32> {
+        Matrix3::from_axis_angle(axis.normalize(), Deg(degrees))
+    }
+
+    fn assert_close(actual: Matrix3<f32>, expected: Matrix3<f32>) {
+        for (a, e) in [
+            (actual.x, expected.x),
+            (actual.y, expected.y),
+            (actual.z, expected.z),
+        ] {
+            assert!((a - e).magnitude() < 1e-5, "{actual:?} is not {expected:?}");
+        }
+    }
+
+    #[test]
+    fn ends_exactly_at_the_endpoints() {
+        let from = rotation(Vector3::new(1.0, 2.0, 3.0), 70.0);
+        let target = rotation(Vector3::new(-2.0, 0.5, 1.0), 140.0);
+        let transition = Transition::new(from, 10.0, 2.0);
+        assert_eq!(transition.matrix(target, 10.0), from);
+        assert_eq!(transition.matrix(target, 9.0), from);
+        assert_eq!(transition.matrix(target, 12.0), target);
+        assert_eq!(transition.matrix(target, 13.0), target);
+        assert!(transition.finished(12.0) && !transition.finished(11.9));
+        // half way in time is half way in angle, eased or not
+        let axis = Vector3::new(0.0, 1.0, 1.0);
+        let transition = Transition::new(rotation(axis, 10.0), 0.0, 1.0);
+        assert_close(
+            transition.matrix(rotation(axis, 110.0), 0.5),
+            rotation(axis, 60.0),
+        );
+    }
+
+    #[test]
+    fn takes_the_short_way_round() {
+        let axis = Vector3::new(0.3, -1.0, 0.2);
+        let mut transition = Transition::new(rotation(axis, 20.0), 0.0, 1.0);
+        // the same rotation, but a negative dot product with the target
+        transition.from_quaternion = -transition.from_quaternion;
+        assert!(
+            transition
+                .from_quaternion
+                .dot(Quaternion::from(rotation(axis, 60.0)))
+                < 0.0
+        );
+        assert_close(
+            transition.matrix(rotation(axis, 60.0), 0.5),
+            rotation(axis, 40.0),
+        );
+    }
+}
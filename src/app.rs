@@ -1,5 +1,8 @@
 type WorldMap = crate::world_map2::WorldMap2;
 
+/// storage key of the [`crate::layout::MapLayout`] preference
+const LAYOUT_KEY: &str = "map_layout";
+
 pub struct App {
     world_map: WorldMap,
 }
@@ -16,20 +19,33 @@ impl App {
                     return eframe::get_value(storage, eframe::APP_KEY).unwrap_or_default();
                 }
         */
-        Self {
-            world_map: WorldMap::new(cc),
+        let mut world_map = WorldMap::new(cc);
+        if let Some(layout) = cc
+            .storage
+            .and_then(|storage| eframe::get_value(storage, LAYOUT_KEY))
+        {
+            world_map.set_layout(layout);
         }
+        Self { world_map }
     }
 }
 
 impl eframe::App for App {
     /// Called by the frame work to save state before shutdown.
-    fn save(&mut self, _storage: &mut dyn eframe::Storage) {
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
         // eframe::set_value(storage, eframe::APP_KEY, self);
+        eframe::set_value(storage, LAYOUT_KEY, &self.world_map.layout());
+    }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        self.world_map.destroy();
     }
 
     /// Called each time the UI needs repainting, which may be many times per second.
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        egui::TopBottomPanel::top("controls")
+            .show(ctx, |ui| ui.horizontal(|ui| self.world_map.controls_ui(ui)));
+        egui::SidePanel::right("route info").show(ctx, |ui| self.world_map.route_info_ui(ui));
         egui::CentralPanel::default().show(ctx, |ui| ui.add(&mut self.world_map));
     }
 }
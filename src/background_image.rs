@@ -1,5 +1,6 @@
+use crate::layers::ImageLayer;
 use crate::remapper::GreatCircleRemapper;
-use crate::world_map::{world_map, ImageProgress, WorldSampler};
+use crate::world_map::{world_map, ImageProgress};
 use egui::{ColorImage, Context, TextureHandle, TextureOptions, Ui};
 use std::sync::{Arc, Mutex};
 use std::thread;
@@ -15,14 +16,14 @@ impl BackgroundImage {
         ui: &Ui,
         width: usize,
         height: usize,
-        world_sampler: Arc<WorldSampler>,
+        layers: Vec<ImageLayer>,
         remapper: Arc<GreatCircleRemapper>,
     ) -> Self {
         let image_pipe = Arc::new(Mutex::new(ImagePipe::new()));
         let ctx = (*ui.ctx()).clone();
         let image_pipe2 = image_pipe.clone();
         thread::spawn(move || {
-            calculate_image(ctx, image_pipe2, width, height, &world_sampler, &remapper);
+            calculate_image(ctx, image_pipe2, width, height, &layers, &remapper);
         });
 
         Self {
@@ -106,12 +107,12 @@ fn calculate_image(
     sink: Arc<Mutex<ImagePipe>>,
     width: usize,
     height: usize,
-    world_sampler: &WorldSampler,
+    layers: &[ImageLayer],
     remapper: &GreatCircleRemapper,
 ) {
     thread::sleep(Duration::from_secs(3));
 
-    let img = world_map(width, height, world_sampler, remapper);
+    let img = world_map(width, height, layers, remapper);
     sink.lock().unwrap().accept(img);
     println!("repaint?");
     ctx.request_repaint()
@@ -0,0 +1,209 @@
+//! Typed positions on the sphere.
+//!
+//! [`LonLat`] is a geographic position in radians, east and north positive.
+//! [`UnitVector`] is a point on the unit sphere.  The conversions between the two use the "world"
+//! cartesian frame that the remapper and fragment.glsl share: `x` points at 0°E 0°N, `y` at
+//! 90°E 0°N and `z` at the **south** pole, because the rows of the source ERP run north to south.
+//!
+//! The source ERP is addressed by "fractions": `x` is `(longitude + 180°) / 360°` and `y` is
+//! `(90° - latitude) / 180°`, measured down from the top of the image.  Texture UV is the same,
+//! since the world texture is uploaded top row first.
+
+use crate::remapper::{scalar, Scalar};
+use cgmath::{InnerSpace, Matrix3, Vector2, Vector3};
+use egui::Vec2;
+use std::ops::Mul;
+
+/// a geographic position, in radians
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LonLat<S = f32> {
+    /// radians east of Greenwich, normally in `-π..=π`
+    pub longitude: S,
+    /// radians north of the equator, in `-π/2..=π/2`
+    pub latitude: S,
+}
+
+impl<S: Scalar> LonLat<S> {
+    pub fn new(longitude: S, latitude: S) -> Self {
+        Self {
+            longitude,
+            latitude,
+        }
+    }
+
+    pub fn from_degrees(longitude: S, latitude: S) -> Self {
+        Self::new(longitude.to_radians(), latitude.to_radians())
+    }
+
+    pub fn longitude_degrees(&self) -> S {
+        self.longitude.to_degrees()
+    }
+
+    pub fn latitude_degrees(&self) -> S {
+        self.latitude.to_degrees()
+    }
+
+    /// the position at a fraction of the source ERP.  See the module documentation.
+    pub fn from_frac(frac: Vector2<S>) -> Self {
+        let half: S = scalar(0.5);
+        Self::new((frac.x - half) * S::TAU(), (half - frac.y) * S::PI())
+    }
+
+    /// the fraction of the source ERP at this position, with `x` wrapped into `0..1`
+    pub fn to_frac(&self) -> Vector2<S> {
+        let half: S = scalar(0.5);
+        let x = self.longitude / S::TAU() + half;
+        Vector2::new(x - x.floor(), half - self.latitude / S::PI())
+    }
+
+    pub fn to_unit_vector(&self) -> UnitVector<S> {
+        let (sin_lon, cos_lon) = self.longitude.sin_cos();
+        let (sin_lat, cos_lat) = self.latitude.sin_cos();
+        UnitVector(Vector3::new(cos_lon * cos_lat, sin_lon * cos_lat, -sin_lat))
+    }
+
+    pub fn cast<T: Scalar>(&self) -> LonLat<T> {
+        LonLat::new(
+            T::from(self.longitude).unwrap(),
+            T::from(self.latitude).unwrap(),
+        )
+    }
+}
+
+impl LonLat<f32> {
+    /// the position at an egui texture coordinate of the source ERP
+    pub fn from_uv(uv: Vec2) -> Self {
+        Self::from_frac(Vector2::new(uv.x, uv.y))
+    }
+
+    /// the egui texture coordinate of this position in the source ERP
+    pub fn to_uv(&self) -> Vec2 {
+        let frac = self.to_frac();
+        Vec2::new(frac.x, frac.y)
+    }
+}
+
+/// a point on the unit sphere.  See the module documentation for the frame.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UnitVector<S = f32>(Vector3<S>);
+
+impl<S: Scalar> UnitVector<S> {
+    /// `vector` scaled to unit length
+    pub fn new(vector: Vector3<S>) -> Self {
+        Self(vector.normalize())
+    }
+
+    pub fn vector(&self) -> Vector3<S> {
+        self.0
+    }
+
+    pub fn to_lon_lat(&self) -> LonLat<S> {
+        let Vector3 { x, y, z } = self.0;
+        LonLat::new(S::atan2(y, x), S::atan2(-z, Vector2::new(x, y).magnitude()))
+    }
+
+    /// radians between two points, along the great circle through them
+    pub fn angle(&self, other: UnitVector<S>) -> S {
+        self.0.angle(other.0).0
+    }
+
+    pub fn cast<T: Scalar>(&self) -> UnitVector<T> {
+        UnitVector(self.0.cast().unwrap())
+    }
+}
+
+impl<S: Scalar> From<LonLat<S>> for UnitVector<S> {
+    fn from(position: LonLat<S>) -> Self {
+        position.to_unit_vector()
+    }
+}
+
+impl<S: Scalar> From<UnitVector<S>> for LonLat<S> {
+    fn from(unit: UnitVector<S>) -> Self {
+        unit.to_lon_lat()
+    }
+}
+
+/// rotate a point.  The matrix must be a rotation for the result to stay on the sphere.
+impl<S: Scalar> Mul<UnitVector<S>> for Matrix3<S> {
+    type Output = UnitVector<S>;
+
+    fn mul(self, unit: UnitVector<S>) -> UnitVector<S> {
+        UnitVector(self * unit.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the difference between two longitudes in radians, however they are wrapped
+    fn longitude_error(a: f64, b: f64) -> f64 {
+        let difference = (a - b).rem_euclid(std::f64::consts::TAU);
+        difference.min(std::f64::consts::TAU - difference)
+    }
+
+    fn positions() -> Vec<LonLat<f64>> {
+        let mut positions = vec![];
+        for latitude in [-90.0, -89.999, -45.0, 0.0, 10.5, 89.999, 90.0] {
+            for longitude in [-180.0, -179.999, -90.0, 0.0, 33.3, 179.999, 180.0] {
+                positions.push(LonLat::from_degrees(longitude, latitude));
+            }
+        }
+        positions
+    }
+
+    #[test]
+    fn world_frame_axes() {
+        let axis = |longitude: f64, latitude: f64| {
+            LonLat::from_degrees(longitude, latitude)
+                .to_unit_vector()
+                .vector()
+        };
+        assert!((axis(0.0, 0.0) - Vector3::unit_x()).magnitude() < 1e-15);
+        assert!((axis(90.0, 0.0) - Vector3::unit_y()).magnitude() < 1e-15);
+        assert!((axis(0.0, -90.0) - Vector3::unit_z()).magnitude() < 1e-15);
+        assert_eq!(
+            LonLat::<f64>::from_frac(Vector2::new(0.0, 0.0)),
+            LonLat::from_degrees(-180.0, 90.0)
+        );
+        assert_eq!(
+            LonLat::<f64>::from_frac(Vector2::new(0.5, 0.5)),
+            LonLat::new(0.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn frac_round_trip() {
+        for position in positions() {
+            let back = LonLat::from_frac(position.to_frac());
+            assert!(longitude_error(back.longitude, position.longitude) < 1e-12);
+            assert!((back.latitude - position.latitude).abs() < 1e-12);
+            let frac = position.to_frac();
+            assert!((0.0..1.0).contains(&frac.x) && (0.0..=1.0).contains(&frac.y));
+        }
+        for x in [0.0, 0.25, 0.5, 0.999] {
+            for y in [0.0, 0.3, 0.5, 1.0] {
+                let frac = Vector2::new(x, y);
+                assert!((LonLat::<f64>::from_frac(frac).to_frac() - frac).magnitude() < 1e-12);
+                let uv = Vec2::new(x as f32, y as f32);
+                assert!((LonLat::from_uv(uv).to_uv() - uv).length() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn unit_vector_round_trip() {
+        for position in positions() {
+            let unit = position.to_unit_vector();
+            assert!((unit.vector().magnitude() - 1.0).abs() < 1e-15);
+            let back = unit.to_lon_lat();
+            assert!((back.latitude - position.latitude).abs() < 1e-12);
+            // the longitude of a pole is arbitrary
+            if position.latitude.abs() < std::f64::consts::FRAC_PI_2 {
+                assert!(longitude_error(back.longitude, position.longitude) < 1e-9);
+            }
+            assert!(back.to_unit_vector().angle(unit) < 1e-12);
+        }
+    }
+}
@@ -0,0 +1,228 @@
+//! The distortion heatmap: a color for the local scale factor at every pixel of the map.
+//!
+//! fragment.glsl works the scale factors out from the screen-space derivatives of the remap,
+//! and [`ScaleFactors::at`] does the same on the CPU from an [`Indicatrix`] for the hover readout.
+//! Both are measured against the scale at the center of the map along the great circle, so a
+//! value of 1 means "as true as the middle of the route".
+
+use crate::coords::LonLat;
+use crate::projection::Projection;
+use crate::tissot::Indicatrix;
+use cgmath::{InnerSpace, Matrix2, Matrix3, SquareMatrix};
+use egui::text::Fonts;
+use egui::{Color32, Pos2, Rect, Shape, Stroke, Ui, Vec2};
+
+/// the error that the legend and the contour lines in fragment.glsl single out
+pub const SCALE_TOLERANCE: f32 = 0.02;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum DistortionHeatmap {
+    #[default]
+    Off,
+    /// how much bigger or smaller areas are drawn
+    Areal,
+    /// the worst of the linear scale factors, in whichever direction is furthest from true
+    Linear,
+}
+
+impl DistortionHeatmap {
+    pub const ALL: [DistortionHeatmap; 3] = [
+        DistortionHeatmap::Off,
+        DistortionHeatmap::Areal,
+        DistortionHeatmap::Linear,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DistortionHeatmap::Off => "no heatmap",
+            DistortionHeatmap::Areal => "areal scale",
+            DistortionHeatmap::Linear => "linear scale",
+        }
+    }
+
+    /// must match the `heatmap` uniform switch in fragment.glsl
+    pub fn shader_index(&self) -> i32 {
+        match self {
+            DistortionHeatmap::Off => 0,
+            DistortionHeatmap::Areal => 1,
+            DistortionHeatmap::Linear => 2,
+        }
+    }
+}
+
+pub fn heatmap_combo_box(ui: &mut Ui, heatmap: &mut DistortionHeatmap) {
+    egui::ComboBox::from_id_source("heatmap")
+        .selected_text(heatmap.name())
+        .show_ui(ui, |ui| {
+            for candidate in DistortionHeatmap::ALL {
+                ui.selectable_value(heatmap, candidate, candidate.name());
+            }
+        });
+}
+
+/// the scale factors at one point, relative to [`reference_scale`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScaleFactors {
+    /// the largest linear scale factor, over all directions
+    pub linear_max: f32,
+    /// the smallest linear scale factor, over all directions
+    pub linear_min: f32,
+    pub areal: f32,
+}
+
+impl ScaleFactors {
+    /// the scale factors at `position` in a view whose rotated frame maps to the world by `matrix`
+    pub fn at(projection: &Projection, matrix: &Matrix3<f32>, position: LonLat) -> Option<Self> {
+        // in f64, since the differences are taken over a small step
+        let matrix = matrix.cast::<f64>().expect("f32 fits in f64");
+        let jacobian = Indicatrix::at(projection, &matrix, position.cast())?.plane_jacobian();
+        let (linear, areal) = reference_scale(projection);
+        let (linear, areal) = (linear as f64, areal as f64);
+        // the singular values of the Jacobian, from its metric tensor
+        let e = jacobian.x.magnitude2();
+        let g = jacobian.y.magnitude2();
+        let f = jacobian.x.dot(jacobian.y);
+        let root = ((e - g) * (e - g) + 4.0 * f * f).sqrt();
+        Some(Self {
+            linear_max: (((e + g + root) / 2.0).sqrt() / linear) as f32,
+            linear_min: (((e + g - root) / 2.0).max(0.0).sqrt() / linear) as f32,
+            areal: (jacobian.determinant().abs() / areal) as f32,
+        })
+    }
+}
+
+/// (linear, areal) scale of the map at its center, in the units of the projection's plane per
+/// radian.  The linear scale is taken along the great circle.  Must be the same as the
+/// `reference_scale` uniform in fragment.glsl.
+pub fn reference_scale(projection: &Projection) -> (f32, f32) {
+    // the rotated frame's (1,0,0) is the center of every projection, and its east is along the
+    // great circle
+    let center = Indicatrix::<f64>::at(projection, &Matrix3::identity(), LonLat::new(0.0, 0.0));
+    let jacobian = center.map_or(Matrix2::identity(), |center| center.plane_jacobian());
+    (
+        jacobian.x.magnitude() as f32,
+        jacobian.determinant().abs() as f32,
+    )
+}
+
+/// the color of a scale factor, `0.5` and below deep blue, `1` white, `2` and above deep red.
+/// Keep in sync with heatmap_color() in fragment.glsl
+pub fn heatmap_color(scale: f32) -> Color32 {
+    let t = scale.log2().clamp(-1.0, 1.0);
+    // stretch the middle so errors of a few percent are already tinted
+    let t = t.signum() * t.abs().sqrt();
+    let far = if t < 0.0 {
+        [0.2, 0.4, 1.0]
+    } else {
+        [1.0, 0.25, 0.2]
+    };
+    let [r, g, b] = far.map(|channel| ((1.0 + (channel - 1.0) * t.abs()) * 255.0) as u8);
+    Color32::from_rgb(r, g, b)
+}
+
+/// a color bar from ×0.5 to ×2 at the bottom left of `rect`, with the tolerance marked
+pub fn legend_shapes(fonts: &Fonts, rect: &Rect, heatmap: DistortionHeatmap) -> Vec<Shape> {
+    if heatmap == DistortionHeatmap::Off {
+        return vec![];
+    }
+    let bar = Rect::from_min_size(
+        Pos2::new(rect.left() + 12.0, rect.bottom() - 40.0),
+        Vec2::new(240.0, 12.0),
+    );
+    let mut shapes = vec![Shape::rect_filled(
+        bar.expand(24.0),
+        4.0,
+        Color32::from_black_alpha(160),
+    )];
+    // position along the bar of a scale factor, log scale
+    let x_of = |scale: f32| bar.left() + (scale.log2() + 1.0) / 2.0 * bar.width();
+    let steps = 120;
+    for i in 0..steps {
+        let left = bar.left() + bar.width() * i as f32 / steps as f32;
+        let right = bar.left() + bar.width() * (i + 1) as f32 / steps as f32;
+        let scale = 2.0_f32.powf((i as f32 + 0.5) / steps as f32 * 2.0 - 1.0);
+        shapes.push(Shape::rect_filled(
+            Rect::from_x_y_ranges(left..=right, bar.y_range()),
+            0.0,
+            heatmap_color(scale),
+        ));
+    }
+    let text_color = Color32::WHITE;
+    let font = egui::FontId::proportional(11.0);
+    for (scale, label) in [(0.5, "×0.5"), (1.0, "×1"), (2.0, "×2")] {
+        shapes.push(Shape::text(
+            fonts,
+            Pos2::new(x_of(scale), bar.bottom() + 2.0),
+            egui::Align2::CENTER_TOP,
+            label,
+            font.clone(),
+            text_color,
+        ));
+    }
+    for scale in [1.0 - SCALE_TOLERANCE, 1.0 + SCALE_TOLERANCE] {
+        let x = x_of(scale);
+        shapes.push(Shape::line_segment(
+            [
+                Pos2::new(x, bar.top() - 3.0),
+                Pos2::new(x, bar.bottom() + 3.0),
+            ],
+            Stroke::new(1.0, Color32::BLACK),
+        ));
+    }
+    shapes.push(Shape::text(
+        fonts,
+        Pos2::new(bar.left(), bar.top() - 2.0),
+        egui::Align2::LEFT_BOTTOM,
+        format!("{}, lines at ±{}%", heatmap.name(), SCALE_TOLERANCE * 100.0),
+        font,
+        text_color,
+    ));
+    shapes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scale_factors(projection: Projection, lon: f32, lat: f32) -> ScaleFactors {
+        ScaleFactors::at(
+            &projection,
+            &Matrix3::identity(),
+            LonLat::from_degrees(lon, lat),
+        )
+        .unwrap_or_else(|| panic!("{}: ({lon}, {lat}) is off the map", projection.name()))
+    }
+
+    fn close(actual: f32, expected: f32) -> bool {
+        (actual / expected - 1.0).abs() < 1e-3
+    }
+
+    #[test]
+    fn scale_factors_match_closed_forms() {
+        for lon in [-150.0, -60.0, 0.0, 45.0, 120.0] {
+            for lat in [-60.0, -30.0, 0.0, 15.0, 50.0] {
+                let secant = 1.0 / f32::to_radians(lat).cos();
+                let lambert = scale_factors(Projection::LambertCylindrical, lon, lat);
+                assert!(close(lambert.areal, 1.0), "{lambert:?} at ({lon}, {lat})");
+                let mercator = scale_factors(Projection::Mercator, lon, lat);
+                assert!(
+                    close(mercator.linear_max, secant),
+                    "{mercator:?} at ({lon}, {lat})"
+                );
+                assert!(
+                    close(mercator.linear_min, secant),
+                    "{mercator:?} at ({lon}, {lat})"
+                );
+                let plate_carree = scale_factors(Projection::Equirectangular, lon, lat);
+                assert!(
+                    close(plate_carree.linear_max, secant),
+                    "{plate_carree:?} at ({lon}, {lat})"
+                );
+                assert!(
+                    close(plate_carree.linear_min, 1.0),
+                    "{plate_carree:?} at ({lon}, {lat})"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! The true shortest path between two anchors on the WGS84 ellipsoid.
+//!
+//! The rest of the crate treats the earth as a sphere, which puts the route up to a few tens of
+//! kilometers away from the ellipsoidal geodesic on long routes.  This module computes the
+//! geodesic with Karney's algorithm (via `geographiclib-rs`) so the two can be compared.
+//! Anchors are [`LonLat`]s, as in [`crate::remapper`].
+
+use crate::coords::{LonLat, UnitVector};
+use crate::remapper::Scalar;
+use cgmath::InnerSpace;
+use geographiclib_rs::{DirectGeodesic, Geodesic, InverseGeodesic};
+
+/// the radius of the sphere used to turn angles on the unit sphere into meters (IUGG mean radius)
+pub const EARTH_MEAN_RADIUS_M: f64 = 6_371_008.8;
+
+pub struct EllipsoidalRoute {
+    /// length of the geodesic on the WGS84 ellipsoid, in meters
+    pub length_m: f64,
+    /// length of the spherical great circle arc between the same anchors, in meters
+    pub spherical_length_m: f64,
+    /// forward azimuth at the first anchor, degrees clockwise from north
+    pub initial_azimuth: f64,
+    /// forward azimuth at the second anchor, degrees clockwise from north
+    pub final_azimuth: f64,
+    /// points along the geodesic, both anchors included
+    pub points: Vec<LonLat<f64>>,
+    /// the largest distance between the geodesic and the spherical great circle, in meters
+    pub max_deviation_m: f64,
+}
+
+impl EllipsoidalRoute {
+    /// the geodesic between two anchors, sampled at `count` evenly spaced points
+    pub fn between<S: Scalar>(anchor1: LonLat<S>, anchor2: LonLat<S>, count: usize) -> Self {
+        let anchor1 = anchor1.cast::<f64>();
+        let anchor2 = anchor2.cast::<f64>();
+        let (lon1, lat1) = (anchor1.longitude_degrees(), anchor1.latitude_degrees());
+        let (lon2, lat2) = (anchor2.longitude_degrees(), anchor2.latitude_degrees());
+
+        let wgs84 = Geodesic::wgs84();
+        let (length_m, initial_azimuth, final_azimuth, _): (f64, f64, f64, f64) =
+            wgs84.inverse(lat1, lon1, lat2, lon2);
+
+        let points: Vec<_> = (0..count)
+            .map(|i| {
+                let distance = length_m * i as f64 / (count - 1).max(1) as f64;
+                let (lat, lon) = wgs84.direct(lat1, lon1, initial_azimuth, distance);
+                LonLat::from_degrees(lon, lat)
+            })
+            .collect();
+
+        let a = anchor1.to_unit_vector();
+        let b = anchor2.to_unit_vector();
+        let max_deviation_m = points
+            .iter()
+            .map(|p| cross_track_angle(a, b, p.to_unit_vector()).abs())
+            .fold(0.0, f64::max)
+            * EARTH_MEAN_RADIUS_M;
+
+        Self {
+            length_m,
+            spherical_length_m: a.angle(b) * EARTH_MEAN_RADIUS_M,
+            initial_azimuth,
+            final_azimuth,
+            points,
+            max_deviation_m,
+        }
+    }
+}
+
+/// signed angle in radians between `p` and the great circle through `a` and `b`
+fn cross_track_angle(a: UnitVector<f64>, b: UnitVector<f64>, p: UnitVector<f64>) -> f64 {
+    let normal = a.vector().cross(b.vector());
+    if normal.magnitude2() < 1e-24 {
+        // the great circle is not defined for coincident or antipodal anchors
+        return 0.0;
+    }
+    normal.normalize().dot(p.vector()).clamp(-1.0, 1.0).asin()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn route_matches_geographiclib() {
+        let wgs84 = Geodesic::wgs84();
+        let london = (-0.1278, 51.5074);
+        let tokyo = (139.6917, 35.6895);
+        let sydney = (151.2093, -33.8688);
+        let santiago = (-70.6693, -33.4489);
+        for (a, b) in [(london, tokyo), (sydney, santiago), (tokyo, santiago)] {
+            let route = EllipsoidalRoute::between(
+                LonLat::<f64>::from_degrees(a.0, a.1),
+                LonLat::from_degrees(b.0, b.1),
+                101,
+            );
+            let (length_m, _, _, _): (f64, f64, f64, f64) = wgs84.inverse(a.1, a.0, b.1, b.0);
+            assert!((route.length_m - length_m).abs() < 1e-6);
+
+            // the midpoint is half way along the geodesic from either end
+            let midpoint = route.points[50];
+            let (lon, lat) = (midpoint.longitude_degrees(), midpoint.latitude_degrees());
+            let from_a: f64 = wgs84.inverse(a.1, a.0, lat, lon);
+            let to_b: f64 = wgs84.inverse(lat, lon, b.1, b.0);
+            assert!((from_a - length_m / 2.0).abs() < 1e-3, "{a:?} to {b:?}");
+            assert!((to_b - length_m / 2.0).abs() < 1e-3, "{a:?} to {b:?}");
+        }
+    }
+
+    #[test]
+    fn count_points_with_both_anchors() {
+        let a = LonLat::<f64>::from_degrees(-74.0, 40.7);
+        let b = LonLat::from_degrees(2.35, 48.86);
+        for count in [2, 3, 10, 200] {
+            let route = EllipsoidalRoute::between(a, b, count);
+            assert_eq!(route.points.len(), count);
+            for (point, anchor) in [(route.points[0], a), (route.points[count - 1], b)] {
+                assert!(point.to_unit_vector().angle(anchor.to_unit_vector()) < 1e-12);
+            }
+        }
+    }
+}
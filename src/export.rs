@@ -0,0 +1,158 @@
+//! Saving the GL map as a PNG at any size, for print.
+//!
+//! [`crate::world2::WorldGLSL::render_offscreen`] draws the map into an offscreen framebuffer,
+//! in tiles if need be.  The overlays that egui draws on screen are drawn over each tile by an
+//! [`OverlayPainter`], a private egui_glow painter, so the image matches what the widget shows.
+
+use crate::layout::MAP_ASPECT;
+use eframe::egui_glow;
+use eframe::glow::Context;
+use egui::epaint::tessellator::tessellate_shapes;
+use egui::epaint::{ClippedShape, TessellationOptions};
+use egui::text::Fonts;
+use egui::{DragValue, Pos2, Rect, Shape, TextureId, Ui};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::sync::Arc;
+
+/// the longest side of an exported image.  The whole image is held in memory, up to 1 GiB here.
+const MAX_EXPORT_SIZE: u32 = 16384;
+
+/// the settings of the "Export image…" menu
+pub(crate) struct ExportOptions {
+    pub width: u32,
+    pub height: u32,
+    pub path: String,
+    /// what happened to the last export
+    pub status: Option<Result<String, String>>,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            width: 8000,
+            height: 4000,
+            path: "great_circle.png".to_owned(),
+            status: None,
+        }
+    }
+}
+
+impl ExportOptions {
+    /// returns true when the user asks for the image
+    pub fn ui(&mut self, ui: &mut Ui) -> bool {
+        ui.horizontal(|ui| {
+            ui.add(DragValue::new(&mut self.width).clamp_range(1..=MAX_EXPORT_SIZE));
+            ui.label("×");
+            ui.add(DragValue::new(&mut self.height).clamp_range(1..=MAX_EXPORT_SIZE));
+            if ui
+                .button("2:1")
+                .on_hover_text("the height that leaves the map undistorted")
+                .clicked()
+            {
+                self.height = ((self.width as f32 / MAP_ASPECT).round() as u32).max(1);
+            }
+        });
+        ui.text_edit_singleline(&mut self.path);
+        let export = ui
+            .button("export")
+            .on_hover_text("the app stops responding while the image is rendered")
+            .clicked();
+        match &self.status {
+            Some(Ok(message)) => {
+                ui.label(message);
+            }
+            Some(Err(error)) => {
+                ui.colored_label(ui.visuals().error_fg_color, error);
+            }
+            None => {}
+        }
+        export
+    }
+}
+
+/// draws egui shapes into whatever framebuffer is bound, one tile of a large image at a time
+pub(crate) struct OverlayPainter {
+    painter: egui_glow::Painter,
+    /// pixels of the image per point of the shapes
+    pixels_per_point: f32,
+    tessellation_options: TessellationOptions,
+    /// the app's fonts, rasterized at `pixels_per_point` so text is as sharp as the lines
+    fonts: Fonts,
+}
+
+impl OverlayPainter {
+    pub fn new(
+        gl: &Arc<Context>,
+        ctx: &egui::Context,
+        pixels_per_point: f32,
+    ) -> Result<Self, String> {
+        let painter =
+            egui_glow::Painter::new(gl.clone(), "", None).map_err(|error| error.to_string())?;
+        let (max_texture_side, definitions) = ctx.fonts(|fonts| {
+            let definitions = fonts.lock().fonts.definitions().clone();
+            (fonts.max_texture_side(), definitions)
+        });
+        Ok(Self {
+            painter,
+            pixels_per_point,
+            tessellation_options: ctx.tessellation_options(|options| *options),
+            fonts: Fonts::new(pixels_per_point, max_texture_side, definitions),
+        })
+    }
+
+    /// for laying out the text of the shapes to paint
+    pub fn fonts(&self) -> &Fonts {
+        &self.fonts
+    }
+
+    /// draw the part of `shapes` that falls in `tile`, a rect in pixels of the whole image
+    pub fn paint(&mut self, shapes: &[Shape], tile: Rect) {
+        let offset = tile.min.to_vec2() / self.pixels_per_point;
+        let clip_rect = Rect::from_min_size(Pos2::ZERO, tile.size() / self.pixels_per_point);
+        let shapes = shapes
+            .iter()
+            .map(|shape| {
+                let mut shape = shape.clone();
+                shape.translate(-offset);
+                ClippedShape { clip_rect, shape }
+            })
+            .collect();
+        // any glyphs laid out since the last tile
+        if let Some(font_image) = self.fonts.font_image_delta() {
+            self.painter.set_texture(TextureId::default(), &font_image);
+        }
+        // not Context::tessellate, which only has fonts for the pixels per point of the screen
+        let primitives = tessellate_shapes(
+            self.pixels_per_point,
+            self.tessellation_options,
+            self.fonts.font_image_size(),
+            self.fonts.texture_atlas().lock().prepared_discs(),
+            shapes,
+        );
+        self.painter.paint_primitives(
+            [tile.width() as u32, tile.height() as u32],
+            self.pixels_per_point,
+            &primitives,
+        );
+    }
+}
+
+impl Drop for OverlayPainter {
+    fn drop(&mut self) {
+        self.painter.destroy();
+    }
+}
+
+/// write RGBA pixels, top row first, as an 8 bit PNG
+pub(crate) fn save_png(path: &Path, [width, height]: [u32; 2], rgba: &[u8]) -> Result<(), String> {
+    let file = File::create(path).map_err(|error| format!("{}: {error}", path.display()))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|error| error.to_string())?;
+    writer
+        .write_image_data(rgba)
+        .map_err(|error| error.to_string())
+}
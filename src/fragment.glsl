@@ -1,14 +1,73 @@
 #version 300 es
 
-precision mediump float;
-uniform sampler2D world;
+// the distortion heatmap differences neighbouring pixels, which needs more than mediump
+precision highp float;
+// must match MAX_LAYERS in layers.rs
+#define MAX_LAYERS 4
+// the visible layers, bottom first, are layer0 to layer(layer_count-1)
+uniform sampler2D layer0;
+uniform sampler2D layer1;
+uniform sampler2D layer2;
+uniform sampler2D layer3;
+uniform int layer_count;
+uniform float layer_opacity[MAX_LAYERS];
+// must match BlendMode::shader_index()
+uniform int layer_blend[MAX_LAYERS];
 uniform mat3 rotation;
+// must match Projection::shader_index()
+uniform int projection;
+// only used by the stereographic projection.  See Projection::field_of_view()
+uniform float field_of_view;
+// must match DistortionHeatmap::shader_index()
+uniform int heatmap;
+// (linear, areal) scale at the center of the map.  See distortion::reference_scale()
+uniform vec2 reference_scale;
 out vec4 out_color;
 in vec2 tex_coord;
 
 #define PI 3.14159
 #define TAU (2.0*PI)
 
+#define EQUIRECTANGULAR 0
+#define MERCATOR 1
+#define MOLLWEIDE 2
+#define HAMMER 3
+#define LAMBERT_CYLINDRICAL 4
+#define AZIMUTHAL_EQUIDISTANT 5
+#define ORTHOGRAPHIC 6
+#define GNOMONIC 7
+#define STEREOGRAPHIC 8
+
+#define BLEND_NORMAL 0
+#define BLEND_MULTIPLY 1
+#define BLEND_SCREEN 2
+#define BLEND_ADD 3
+
+#define HEATMAP_OFF 0
+#define HEATMAP_AREAL 1
+#define HEATMAP_LINEAR 2
+// see SCALE_TOLERANCE in distortion.rs
+#define SCALE_TOLERANCE 0.02
+
+#define SQRT_2 1.41421356
+// see ELLIPSE_SCALE in projection.rs
+#define ELLIPSE_SCALE (PI / (2.0*SQRT_2))
+// see AZIMUTHAL_EQUIDISTANT_SCALE in projection.rs
+#define AZIMUTHAL_EQUIDISTANT_SCALE 0.5
+// see GLOBE_TILT and GLOBE_RADIUS in projection.rs
+#define GLOBE_TILT radians(25.0)
+#define GLOBE_RADIUS (0.5*PI)
+// see GNOMONIC_LIMIT and GNOMONIC_SCALE in projection.rs
+#define GNOMONIC_LIMIT radians(70.0)
+#define GNOMONIC_SCALE (0.5*PI / sqrt(3.0))
+
+const vec4 great_circle_color = vec4(1.0, 0.8, 0.0, 1.0);
+const vec4 boundary_color = vec4(0.45, 0.45, 0.5, 1.0);
+const vec4 tolerance_color = vec4(0.0, 0.0, 0.0, 1.0);
+
+// must match OFF_MAP_RGBA in world_map.rs
+const vec4 off_map = vec4(0x20, 0x20, 0x28, 0xff) / 255.0;
+
 vec2 frac_to_radians(float longitude_frac, float latitude_frac)
 {
     float theta = longitude_frac * TAU;
@@ -39,18 +98,211 @@ vec2 cartesian_to_lat_long(vec3 xyz)
     float v = phi / PI + 0.5;
     float u = theta / TAU;
 
-    return vec2(my_fmod(u, 1.0), my_fmod(v, 1.0));
+    // u wraps (the texture repeats), but v stops at the poles (the texture clamps)
+    return vec2(my_fmod(u, 1.0), clamp(v, 0.0, 1.0));
+}
+
+// a derivative of u without the jump of ±1 where u wraps at the antimeridian.
+// No real derivative gets near half way round the texture.
+vec2 unwrap_gradient(vec2 gradient)
+{
+    return vec2(gradient.x - floor(gradient.x + 0.5), gradient.y);
+}
+
+vec3 longitude_latitude_to_cartesian(float longitude, float latitude)
+{
+    float r = cos(latitude);
+    return vec3(cos(longitude) * r, sin(longitude) * r, sin(latitude));
 }
 
-vec2 remap(vec2 src, mat3 matrix)
+// must match BlendMode::blend()
+vec3 blend(vec3 below, vec3 above, int mode)
 {
-    vec2 theta_phi = frac_to_radians(src.x, src.y);
+    if (mode == BLEND_MULTIPLY) {
+        return below * above;
+    }
+    if (mode == BLEND_SCREEN) {
+        return 1.0 - (1.0 - below) * (1.0 - above);
+    }
+    if (mode == BLEND_ADD) {
+        return min(below + above, 1.0);
+    }
+    return above;
+}
 
-    vec3 xyz = spherical_to_cartesian(theta_phi.x, theta_phi.y);
+// layer number i, if it is one of the visible ones, over `below`.  See layers::composite()
+vec3 composite(vec3 below, int i, sampler2D layer, vec2 uv, vec2 dx, vec2 dy)
+{
+    if (i >= layer_count) {
+        return below;
+    }
+    // premultiplied, so filtering weights the texels by alpha, but blended straight
+    vec4 color = textureGrad(layer, uv, dx, dy);
+    if (color.a <= 0.0) {
+        return below;
+    }
+    vec3 above = min(color.rgb / color.a, 1.0);
+    return mix(below, blend(below, above, layer_blend[i]), color.a * layer_opacity[i]);
+}
 
-    return cartesian_to_lat_long(matrix * xyz);
+// must match distortion::heatmap_color()
+vec4 heatmap_color(float scale)
+{
+    float t = clamp(log2(scale), -1.0, 1.0);
+    t = sign(t) * sqrt(abs(t));
+    vec3 far = t < 0.0 ? vec3(0.2, 0.4, 1.0) : vec3(1.0, 0.25, 0.2);
+    return vec4(mix(vec3(1.0), far, abs(t)), 1.0);
+}
+
+// map a fraction of the widget to a unit vector in the rotated frame.  See Projection::unproject()
+bool unproject(vec2 src, out vec3 xyz)
+{
+    vec2 plane = (src - 0.5) * vec2(TAU, PI);
+    xyz = vec3(1.0, 0.0, 0.0);
+
+    if (projection == MERCATOR) {
+        xyz = longitude_latitude_to_cartesian(plane.x, 2.0 * atan(exp(plane.y)) - 0.5*PI);
+        return true;
+    }
+
+    if (projection == MOLLWEIDE) {
+        vec2 xy = plane / ELLIPSE_SCALE;
+        float sin_theta = xy.y / SQRT_2;
+        if (abs(sin_theta) > 1.0) {
+            return false;
+        }
+        float theta = asin(sin_theta);
+        float longitude = PI * xy.x / (2.0 * SQRT_2 * cos(theta));
+        if (abs(longitude) > PI) {
+            return false;
+        }
+        float latitude = asin(clamp((2.0 * theta + sin(2.0 * theta)) / PI, -1.0, 1.0));
+        xyz = longitude_latitude_to_cartesian(longitude, latitude);
+        return true;
+    }
+
+    if (projection == HAMMER) {
+        vec2 xy = plane / ELLIPSE_SCALE;
+        if (xy.x * xy.x / 8.0 + xy.y * xy.y / 2.0 > 1.0) {
+            return false;
+        }
+        float z = sqrt(1.0 - xy.x * xy.x / 16.0 - xy.y * xy.y / 4.0);
+        float longitude = 2.0 * atan(z * xy.x, 2.0 * (2.0 * z * z - 1.0));
+        float latitude = asin(clamp(z * xy.y, -1.0, 1.0));
+        xyz = longitude_latitude_to_cartesian(longitude, latitude);
+        return true;
+    }
+
+    if (projection == LAMBERT_CYLINDRICAL) {
+        xyz = longitude_latitude_to_cartesian(plane.x, asin(clamp(plane.y / (0.5*PI), -1.0, 1.0)));
+        return true;
+    }
+
+    if (projection == AZIMUTHAL_EQUIDISTANT) {
+        vec2 p = plane / AZIMUTHAL_EQUIDISTANT_SCALE;
+        float c = length(p);
+        if (c > PI) {
+            return false;
+        }
+        if (c < 1e-6) {
+            xyz = vec3(1.0, 0.0, 0.0);
+            return true;
+        }
+        xyz = vec3(cos(c), p * (sin(c) / c));
+        return true;
+    }
+
+    if (projection == ORTHOGRAPHIC) {
+        vec2 p = plane / GLOBE_RADIUS;
+        float r2 = dot(p, p);
+        if (r2 > 1.0) {
+            return false;
+        }
+        vec3 towards = vec3(cos(GLOBE_TILT), 0.0, -sin(GLOBE_TILT));
+        vec3 right = vec3(0.0, 1.0, 0.0);
+        vec3 down = vec3(sin(GLOBE_TILT), 0.0, cos(GLOBE_TILT));
+        xyz = towards * sqrt(1.0 - r2) + right * p.x + down * p.y;
+        return true;
+    }
+
+    if (projection == GNOMONIC) {
+        vec2 p = plane / GNOMONIC_SCALE;
+        if (length(p) > tan(GNOMONIC_LIMIT)) {
+            return false;
+        }
+        xyz = normalize(vec3(1.0, p));
+        return true;
+    }
+
+    if (projection == STEREOGRAPHIC) {
+        // see stereographic_scale() in projection.rs
+        float scale = 0.5*PI / (2.0 * tan(field_of_view * 0.25));
+        vec2 p = plane / (2.0 * scale);
+        float r2 = dot(p, p);
+        xyz = vec3(1.0 - r2, 2.0 * p) / (1.0 + r2);
+        return true;
+    }
+
+    xyz = longitude_latitude_to_cartesian(plane.x, plane.y);
+    return true;
 }
 
 void main() {
-    out_color = texture(world, remap(tex_coord, rotation));
+    vec3 xyz;
+    bool on_map = unproject(tex_coord, xyz);
+    // take the derivatives outside of any branch so they stay defined
+    vec2 uv = cartesian_to_lat_long(rotation * xyz);
+    vec2 dx = unwrap_gradient(dFdx(uv));
+    vec2 dy = unwrap_gradient(dFdy(uv));
+    // sampler arrays can only take constant indices, so each layer is named
+    vec3 color = vec3(0.0);
+    color = composite(color, 0, layer0, uv, dx, dy);
+    color = composite(color, 1, layer1, uv, dx, dy);
+    color = composite(color, 2, layer2, uv, dx, dy);
+    color = composite(color, 3, layer3, uv, dx, dy);
+    out_color = on_map ? vec4(color, 1.0) : off_map;
+
+    // the sphere radians per unit of the projection's plane, along the two screen axes.
+    // These are central differences over one pixel; dFdx() and dFdy() are one-sided and make a
+    // checkerboard where the map bends quickly.
+    vec2 pixel = vec2(dFdx(tex_coord.x), dFdy(tex_coord.y));
+    vec3 left, right, above, below;
+    unproject(tex_coord - vec2(0.5 * pixel.x, 0.0), left);
+    unproject(tex_coord + vec2(0.5 * pixel.x, 0.0), right);
+    unproject(tex_coord - vec2(0.0, 0.5 * pixel.y), above);
+    unproject(tex_coord + vec2(0.0, 0.5 * pixel.y), below);
+    vec3 along_x = (right - left) / (pixel.x * TAU);
+    vec3 along_y = (below - above) / (pixel.y * PI);
+    float e = dot(along_x, along_x);
+    float f = dot(along_x, along_y);
+    float g = dot(along_y, along_y);
+    float root = sqrt((e - g) * (e - g) + 4.0 * f * f);
+    // the map's scale factors are the reciprocals of the singular values of the inverse map
+    float linear_max = inversesqrt(max(0.5 * (e + g - root), 1e-20)) / reference_scale.x;
+    float linear_min = inversesqrt(0.5 * (e + g + root)) / reference_scale.x;
+    float areal = inversesqrt(max(e * g - f * f, 1e-20)) / reference_scale.y;
+    float scale = heatmap == HEATMAP_AREAL ? areal
+        : linear_max * linear_min > 1.0 ? linear_max : linear_min;
+    float error = abs(scale - 1.0) - SCALE_TOLERANCE;
+    float error_width = fwidth(error);
+    float contour = 1.0 - smoothstep(error_width, 2.0 * error_width, abs(error));
+    if (heatmap != HEATMAP_OFF && on_map) {
+        out_color = mix(out_color, heatmap_color(scale), 0.6);
+        out_color = mix(out_color, tolerance_color, 0.7 * contour);
+    }
+
+    if (projection == ORTHOGRAPHIC) {
+        // the equator of the rotated frame is the great circle through the anchors
+        float width = min(1.5 * fwidth(xyz.z), 0.01);
+        float ring = 1.0 - smoothstep(width, 2.0 * width, abs(xyz.z));
+        out_color = mix(out_color, great_circle_color, on_map ? ring : 0.0);
+    }
+
+    if (projection == GNOMONIC) {
+        // outline the edge of the map, which is the circle GNOMONIC_LIMIT from the center
+        vec2 plane = (tex_coord - 0.5) * vec2(TAU, PI);
+        float edge = length(plane) - GNOMONIC_SCALE * tan(GNOMONIC_LIMIT);
+        float width = fwidth(edge);
+        out_color = mix(out_color, boundary_color, 1.0 - smoothstep(width, 2.0 * width, abs(edge)));
+    }
 }
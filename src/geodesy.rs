@@ -0,0 +1,568 @@
+//! Facts about the great circle route between two anchors, on a spherical earth.
+//!
+//! Anchors and results are [`LonLat`]s, as in [`crate::remapper`]; bearings are in degrees.
+//! For the ellipsoidal version of the distance see [`crate::ellipsoid`].
+
+use crate::coords::{LonLat, UnitVector};
+use crate::ellipsoid::EARTH_MEAN_RADIUS_M;
+use crate::remapper::{matrix_from_anchors, AnchorError, Scalar};
+use cgmath::{InnerSpace, Matrix, Vector3};
+use egui::Ui;
+use std::f64::consts::TAU;
+
+pub const METERS_PER_NAUTICAL_MILE: f64 = 1852.0;
+pub const METERS_PER_MILE: f64 = 1609.344;
+
+/// a place on or near the route
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RoutePoint {
+    pub position: LonLat<f64>,
+    /// false if the point is on the rest of the great circle, not between the anchors
+    pub on_route: bool,
+}
+
+pub struct RouteInfo {
+    /// along the great circle, on a sphere of [`EARTH_MEAN_RADIUS_M`]
+    pub distance_m: f64,
+    /// degrees clockwise from north, leaving the first anchor
+    pub initial_bearing: f64,
+    /// degrees clockwise from north, arriving at the second anchor
+    pub final_bearing: f64,
+    pub midpoint: RoutePoint,
+    /// the northernmost and southernmost points of the great circle, if it is not the equator
+    pub vertices: Option<[RoutePoint; 2]>,
+    /// where the great circle crosses the equator, if it is not the equator
+    pub equator_crossings: Option<[RoutePoint; 2]>,
+}
+
+impl RouteInfo {
+    pub fn between<S: Scalar>(anchor1: LonLat<S>, anchor2: LonLat<S>) -> Result<Self, AnchorError> {
+        let anchor1 = anchor1.cast::<f64>();
+        let anchor2 = anchor2.cast::<f64>();
+        let a = anchor1.to_unit_vector().vector();
+        let b = anchor2.to_unit_vector().vector();
+        let normal = a.cross(b);
+        if normal.magnitude() < 1e-9 {
+            return Err(if a.dot(b) > 0.0 {
+                AnchorError::Coincident
+            } else {
+                AnchorError::Antipodal
+            });
+        }
+        let normal = normal.normalize();
+        let route_point = |xyz: Vector3<f64>| {
+            // between the anchors if it is on the forward side of both
+            let after_a = a.cross(xyz).dot(normal) >= -1e-12;
+            let before_b = xyz.cross(b).dot(normal) >= -1e-12;
+            to_route_point(xyz, after_a && before_b)
+        };
+
+        // our cartesian z points south, see crate::coords
+        let north = -Vector3::unit_z();
+        let towards_north = north - normal * north.dot(normal);
+        let (vertices, equator_crossings) = if towards_north.magnitude() < 1e-9 {
+            (None, None)
+        } else {
+            let vertex = towards_north.normalize();
+            let node = normal.cross(north).normalize();
+            (
+                Some([route_point(vertex), route_point(-vertex)]),
+                Some([route_point(node), route_point(-node)]),
+            )
+        };
+
+        Ok(Self {
+            distance_m: a.angle(b).0 * EARTH_MEAN_RADIUS_M,
+            initial_bearing: bearing(anchor1, anchor2),
+            final_bearing: (bearing(anchor2, anchor1) + 180.0).rem_euclid(360.0),
+            midpoint: to_route_point((a + b).normalize(), true),
+            vertices,
+            equator_crossings,
+        })
+    }
+
+    pub fn distance_km(&self) -> f64 {
+        self.distance_m / 1000.0
+    }
+
+    pub fn distance_nautical_miles(&self) -> f64 {
+        self.distance_m / METERS_PER_NAUTICAL_MILE
+    }
+
+    pub fn distance_miles(&self) -> f64 {
+        self.distance_m / METERS_PER_MILE
+    }
+}
+
+/// the loxodrome between two anchors, the path of constant heading
+pub struct RhumbLine {
+    /// on a sphere of [`EARTH_MEAN_RADIUS_M`]
+    pub length_m: f64,
+    /// the great circle distance between the same anchors, for comparison
+    pub great_circle_m: f64,
+    /// degrees clockwise from north, the same all the way
+    pub bearing: f64,
+    /// points along the rhumb line, both anchors included
+    pub points: Vec<LonLat<f64>>,
+}
+
+impl RhumbLine {
+    /// the shorter rhumb line between two anchors, sampled at `count` evenly spaced points
+    pub fn between<S: Scalar>(anchor1: LonLat<S>, anchor2: LonLat<S>, count: usize) -> Self {
+        let anchor1 = anchor1.cast::<f64>();
+        let anchor2 = anchor2.cast::<f64>();
+        // the rhumb line spirals into the poles, so keep just short of them
+        let max_lat = 89.9999_f64.to_radians();
+        let lat1 = anchor1.latitude.clamp(-max_lat, max_lat);
+        let lat2 = anchor2.latitude.clamp(-max_lat, max_lat);
+        let lon1 = anchor1.longitude;
+        let delta_lon = ((anchor2.longitude - lon1) + TAU / 2.0).rem_euclid(TAU) - TAU / 2.0;
+        let delta_lat = lat2 - lat1;
+
+        // the rhumb line is straight on a Mercator map, with these y coordinates
+        let mercator_y = |lat: f64| (TAU / 8.0 + lat / 2.0).tan().ln();
+        let y1 = mercator_y(lat1);
+        let delta_y = mercator_y(lat2) - y1;
+        let along_parallel = delta_y.abs() < 1e-12;
+        // the ratio of latitude change to distance-scaled latitude change
+        let q = if along_parallel {
+            lat1.cos()
+        } else {
+            delta_lat / delta_y
+        };
+
+        let points = (0..count)
+            .map(|i| {
+                let t = i as f64 / (count - 1).max(1) as f64;
+                // latitude changes at a constant rate along the line, longitude follows Mercator y
+                let lat = lat1 + t * delta_lat;
+                let lon = if along_parallel {
+                    lon1 + t * delta_lon
+                } else {
+                    lon1 + delta_lon * (mercator_y(lat) - y1) / delta_y
+                };
+                LonLat::new(lon, lat)
+            })
+            .collect();
+
+        let a = anchor1.to_unit_vector();
+        let b = anchor2.to_unit_vector();
+        Self {
+            length_m: (delta_lat * delta_lat + q * q * delta_lon * delta_lon).sqrt()
+                * EARTH_MEAN_RADIUS_M,
+            great_circle_m: a.angle(b) * EARTH_MEAN_RADIUS_M,
+            bearing: delta_lon.atan2(delta_y).to_degrees().rem_euclid(360.0),
+            points,
+        }
+    }
+}
+
+/// how to place waypoints between the anchors.  The anchors themselves are always included.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WaypointSpacing {
+    /// this many points, evenly spaced
+    Count(usize),
+    /// a point every so many kilometers from the first anchor
+    Distance { km: f64 },
+    /// a point wherever the route crosses a meridian that is a multiple of so many degrees
+    Meridians { degrees: f64 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Waypoint {
+    pub position: LonLat<f64>,
+    /// along the route from the first anchor
+    pub distance_m: f64,
+}
+
+/// points along the great circle route from `anchor1` to `anchor2`, in order.
+///
+/// In the frame of [`matrix_from_anchors`] the route is the stretch of the equator from
+/// `-half` to `half` radians of longitude, so all the spacings are worked out there.
+pub fn waypoints<S: Scalar>(
+    anchor1: LonLat<S>,
+    anchor2: LonLat<S>,
+    spacing: WaypointSpacing,
+) -> Result<Vec<Waypoint>, AnchorError> {
+    let anchor1 = anchor1.cast::<f64>();
+    let anchor2 = anchor2.cast::<f64>();
+    let matrix = matrix_from_anchors(&[anchor1, anchor2])?;
+    let half = anchor1.to_unit_vector().angle(anchor2.to_unit_vector()) / 2.0;
+    let at = |t: f64| matrix * Vector3::new(t.cos(), t.sin(), 0.0);
+    let waypoint = |t: f64| Waypoint {
+        position: UnitVector::new(at(t)).to_lon_lat(),
+        distance_m: (t + half) * EARTH_MEAN_RADIUS_M,
+    };
+
+    let mut points: Vec<Waypoint> = match spacing {
+        WaypointSpacing::Count(count) => {
+            let count = count.max(2);
+            (0..count)
+                .map(|i| waypoint(-half + 2.0 * half * i as f64 / (count - 1) as f64))
+                .collect()
+        }
+        WaypointSpacing::Distance { km } => {
+            let step = km * 1000.0 / EARTH_MEAN_RADIUS_M;
+            // a route within rounding of a whole number of steps ends on the last step, instead
+            // of a second waypoint a hair's breadth after it
+            let count = (2.0 * half / step - 1e-9).ceil().max(1.0) as usize;
+            (0..count)
+                .map(|i| waypoint(-half + step * i as f64))
+                .chain(std::iter::once(waypoint(half)))
+                .collect()
+        }
+        WaypointSpacing::Meridians { degrees } => {
+            // whole multiples of the spacing, so the crossings land on round longitudes
+            let first = (-180.0 / degrees).ceil() as i64;
+            let mut last = (180.0 / degrees).floor() as i64;
+            if first as f64 * degrees <= -180.0 && last as f64 * degrees >= 180.0 {
+                // ±180 is the same meridian
+                last -= 1;
+            }
+            let mut crossings: Vec<(f64, f64)> = (first..=last)
+                .filter_map(|k| {
+                    let longitude = (degrees * k as f64).to_radians();
+                    let (sin, cos) = longitude.sin_cos();
+                    // the meridian is half of the great circle with this normal
+                    let normal = matrix.transpose() * Vector3::new(-sin, cos, 0.0);
+                    let t = f64::atan2(-normal.x, normal.y);
+                    let t = if at(t).dot(Vector3::new(cos, sin, 0.0)) < 0.0 {
+                        t + TAU / 2.0
+                    } else {
+                        t
+                    };
+                    let t = (t + TAU / 2.0).rem_euclid(TAU) - TAU / 2.0;
+                    // leave out anchors that sit on a meridian, they are added below
+                    (t.abs() < half - 1e-9).then_some((t, longitude))
+                })
+                .collect();
+            crossings.sort_by(|a, b| a.0.total_cmp(&b.0));
+            std::iter::once(waypoint(-half))
+                .chain(crossings.into_iter().map(|(t, longitude)| {
+                    let waypoint = waypoint(t);
+                    Waypoint {
+                        position: LonLat::new(longitude, waypoint.position.latitude),
+                        ..waypoint
+                    }
+                }))
+                .chain(std::iter::once(waypoint(half)))
+                .collect()
+        }
+    };
+    // keep the anchors exactly where they were clicked
+    points[0].position = anchor1;
+    let last = points.len() - 1;
+    points[last].position = anchor2;
+    Ok(points)
+}
+
+/// one line per waypoint, with a header, for pasting into other tools
+pub fn waypoints_csv(waypoints: &[Waypoint]) -> String {
+    let mut csv = "index,longitude,latitude,distance_km\n".to_string();
+    for (i, waypoint) in waypoints.iter().enumerate() {
+        csv += &format!(
+            "{},{:.6},{:.6},{:.3}\n",
+            i,
+            waypoint.position.longitude_degrees(),
+            waypoint.position.latitude_degrees(),
+            waypoint.distance_m / 1000.0
+        );
+    }
+    csv
+}
+
+/// initial bearing in degrees clockwise from north of the great circle from `from` to `to`
+pub fn bearing(from: LonLat<f64>, to: LonLat<f64>) -> f64 {
+    let (lat1, lat2) = (from.latitude, to.latitude);
+    let delta = to.longitude - from.longitude;
+    let y = delta.sin() * lat2.cos();
+    let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * delta.cos();
+    y.atan2(x).to_degrees().rem_euclid(360.0)
+}
+
+fn to_route_point(xyz: Vector3<f64>, on_route: bool) -> RoutePoint {
+    RoutePoint {
+        position: UnitVector::new(xyz).to_lon_lat(),
+        on_route,
+    }
+}
+
+/// the waypoint settings of a map widget
+pub(crate) struct WaypointOptions {
+    pub spacing: WaypointSpacing,
+    /// draw the waypoints on the map
+    pub show: bool,
+}
+
+impl Default for WaypointOptions {
+    fn default() -> Self {
+        Self {
+            spacing: WaypointSpacing::Count(10),
+            show: false,
+        }
+    }
+}
+
+impl WaypointOptions {
+    fn waypoints(&self, anchors: &[LonLat]) -> Vec<Waypoint> {
+        match anchors[..] {
+            [anchor1, anchor2, ..] => waypoints(anchor1, anchor2, self.spacing).unwrap_or_default(),
+            _ => vec![],
+        }
+    }
+
+    /// the waypoints to draw on the map
+    pub fn markers(&self, anchors: &[LonLat]) -> Vec<LonLat> {
+        if !self.show {
+            return vec![];
+        }
+        self.waypoints(anchors)
+            .iter()
+            .map(|waypoint| waypoint.position.cast())
+            .collect()
+    }
+
+    fn ui(&mut self, ui: &mut Ui, anchors: &[LonLat]) {
+        ui.horizontal(|ui| {
+            let spacing = &mut self.spacing;
+            if ui
+                .radio(matches!(spacing, WaypointSpacing::Count(_)), "count")
+                .clicked()
+            {
+                *spacing = WaypointSpacing::Count(10);
+            }
+            if ui
+                .radio(
+                    matches!(spacing, WaypointSpacing::Distance { .. }),
+                    "every km",
+                )
+                .clicked()
+            {
+                *spacing = WaypointSpacing::Distance { km: 500.0 };
+            }
+            if ui
+                .radio(
+                    matches!(spacing, WaypointSpacing::Meridians { .. }),
+                    "meridians",
+                )
+                .clicked()
+            {
+                *spacing = WaypointSpacing::Meridians { degrees: 10.0 };
+            }
+        });
+        match &mut self.spacing {
+            WaypointSpacing::Count(count) => {
+                ui.add(
+                    egui::DragValue::new(count)
+                        .clamp_range(2..=1000)
+                        .suffix(" points"),
+                );
+            }
+            WaypointSpacing::Distance { km } => {
+                ui.add(
+                    egui::DragValue::new(km)
+                        .clamp_range(10.0..=20000.0)
+                        .suffix(" km"),
+                );
+            }
+            WaypointSpacing::Meridians { degrees } => {
+                ui.add(
+                    egui::DragValue::new(degrees)
+                        .speed(0.1)
+                        .clamp_range(0.5..=90.0)
+                        .suffix("°"),
+                );
+            }
+        }
+
+        let waypoints = self.waypoints(anchors);
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.show, "show on map");
+            if ui.button("copy CSV").clicked() {
+                ui.output_mut(|output| output.copied_text = waypoints_csv(&waypoints));
+            }
+        });
+        ui.label(format!("{} waypoints", waypoints.len()));
+    }
+}
+
+/// the route info panel for the first two `anchors`
+pub(crate) fn route_info_ui(
+    ui: &mut Ui,
+    anchors: &[LonLat],
+    waypoint_options: &mut WaypointOptions,
+) {
+    ui.heading("route");
+    let [anchor1, anchor2, ..] = anchors[..] else {
+        ui.label("click two points on the map");
+        return;
+    };
+    let info = match RouteInfo::between(anchor1, anchor2) {
+        Ok(info) => info,
+        Err(error) => {
+            ui.label(error.to_string());
+            return;
+        }
+    };
+
+    egui::Grid::new("route info").num_columns(2).show(ui, |ui| {
+        ui.label("distance");
+        ui.label(format!(
+            "{:.1} km\n{:.1} nmi\n{:.1} mi",
+            info.distance_km(),
+            info.distance_nautical_miles(),
+            info.distance_miles()
+        ));
+        ui.end_row();
+        ui.label("initial bearing");
+        ui.label(format!("{:.2}°", info.initial_bearing));
+        ui.end_row();
+        ui.label("final bearing");
+        ui.label(format!("{:.2}°", info.final_bearing));
+        ui.end_row();
+        ui.label("midpoint");
+        ui.label(point_text(&info.midpoint));
+        ui.end_row();
+        for (label, points) in [
+            ("vertices", info.vertices),
+            ("equator crossings", info.equator_crossings),
+        ] {
+            ui.label(label);
+            match points {
+                Some(points) => {
+                    ui.label(points.iter().map(point_text).collect::<Vec<_>>().join("\n"))
+                }
+                None => ui.label("the route follows the equator"),
+            };
+            ui.end_row();
+        }
+    });
+
+    ui.separator();
+    ui.heading("waypoints");
+    waypoint_options.ui(ui, anchors);
+}
+
+fn point_text(point: &RoutePoint) -> String {
+    format!(
+        "{:.3}°, {:.3}°{}",
+        point.position.longitude_degrees(),
+        point.position.latitude_degrees(),
+        if point.on_route { "" } else { " (off route)" }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geographiclib_rs::{Geodesic, InverseGeodesic};
+
+    /// relative, for distances, and in degrees, for angles
+    const TOLERANCE: f64 = 1e-9;
+
+    /// the difference between two bearings in degrees, however they are wrapped
+    fn bearing_error(a: f64, b: f64) -> f64 {
+        let difference = (a - b).rem_euclid(360.0);
+        difference.min(360.0 - difference)
+    }
+
+    #[test]
+    fn route_matches_geographiclib() {
+        let sphere = Geodesic::new(EARTH_MEAN_RADIUS_M, 0.0);
+        let london = (-0.1278, 51.5074);
+        let tokyo = (139.6917, 35.6895);
+        let sydney = (151.2093, -33.8688);
+        let santiago = (-70.6693, -33.4489);
+        for (a, b) in [(london, tokyo), (tokyo, london), (sydney, santiago)] {
+            let route = RouteInfo::between(
+                LonLat::<f64>::from_degrees(a.0, a.1),
+                LonLat::from_degrees(b.0, b.1),
+            )
+            .unwrap();
+            let (distance_m, azimuth1, azimuth2, _): (f64, f64, f64, f64) =
+                sphere.inverse(a.1, a.0, b.1, b.0);
+            assert!(
+                (route.distance_m / distance_m - 1.0).abs() < TOLERANCE,
+                "{a:?} to {b:?}"
+            );
+            assert!(bearing_error(route.initial_bearing, azimuth1) < TOLERANCE);
+            assert!(bearing_error(route.final_bearing, azimuth2) < TOLERANCE);
+        }
+    }
+
+    #[test]
+    fn meridian_crossings_are_round_longitudes() {
+        // across the antimeridian, where ±180 must only be crossed once
+        let anchor1 = LonLat::<f64>::from_degrees(150.0, 10.0);
+        let anchor2 = LonLat::from_degrees(-150.0, 20.0);
+        // 154° to 203°, and 160° to 200°, strictly between the anchors
+        for (degrees, count) in [(7.0, 8), (10.0, 5)] {
+            let points =
+                waypoints(anchor1, anchor2, WaypointSpacing::Meridians { degrees }).unwrap();
+            let crossings = &points[1..points.len() - 1];
+            assert_eq!(crossings.len(), count);
+            for point in crossings {
+                let multiple = point.position.longitude_degrees() / degrees;
+                assert!((multiple - multiple.round()).abs() < TOLERANCE, "{point:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn distance_spacing_has_no_duplicate_endpoint() {
+        let anchor1 = LonLat::<f64>::from_degrees(-0.1278, 51.5074);
+        let anchor2 = LonLat::from_degrees(139.6917, 35.6895);
+        let km = RouteInfo::between(anchor1, anchor2).unwrap().distance_m / 1000.0;
+        for steps in [3.0, 7.0] {
+            for nudge in [-1e-12, 0.0, 1e-12] {
+                let spacing = WaypointSpacing::Distance {
+                    km: km / steps * (1.0 + nudge),
+                };
+                let points = waypoints(anchor1, anchor2, spacing).unwrap();
+                assert_eq!(points.len(), steps as usize + 1, "nudged by {nudge}");
+            }
+        }
+        // and a route that is not a whole number of steps keeps its short last leg
+        let points = waypoints(anchor1, anchor2, WaypointSpacing::Distance { km: km / 2.5 });
+        assert_eq!(points.unwrap().len(), 4);
+    }
+
+    #[test]
+    fn rhumb_lines_along_parallels_and_meridians() {
+        let rhumb = |from: (f64, f64), to: (f64, f64)| {
+            RhumbLine::between(
+                LonLat::<f64>::from_degrees(from.0, from.1),
+                LonLat::from_degrees(to.0, to.1),
+                10,
+            )
+        };
+        let meters = |degrees: f64| degrees.to_radians() * EARTH_MEAN_RADIUS_M;
+
+        let east = rhumb((10.0, 40.0), (40.0, 40.0));
+        let expected = meters(30.0) * 40.0_f64.to_radians().cos();
+        assert!((east.length_m / expected - 1.0).abs() < TOLERANCE);
+        assert!(bearing_error(east.bearing, 90.0) < TOLERANCE);
+
+        let north = rhumb((20.0, 10.0), (20.0, 50.0));
+        assert!((north.length_m / meters(40.0) - 1.0).abs() < TOLERANCE);
+        assert!(bearing_error(north.bearing, 0.0) < TOLERANCE);
+
+        // the short way round across the antimeridian, not 340° the other way
+        let across = rhumb((170.0, -30.0), (-170.0, -30.0));
+        let expected = meters(20.0) * 30.0_f64.to_radians().cos();
+        assert!((across.length_m / expected - 1.0).abs() < TOLERANCE);
+        assert!(bearing_error(across.bearing, 90.0) < TOLERANCE);
+        let back = rhumb((-170.0, -30.0), (170.0, -30.0));
+        assert!(bearing_error(back.bearing, 270.0) < TOLERANCE);
+        let climbing = rhumb((170.0, 10.0), (-170.0, 20.0));
+        assert!(climbing.bearing > 0.0 && climbing.bearing < 90.0);
+        assert!(climbing.length_m < meters(30.0));
+        for point in &climbing.points {
+            let longitude = point.longitude_degrees().rem_euclid(360.0);
+            assert!(
+                (170.0 - 1e-9..=190.0 + 1e-9).contains(&longitude),
+                "{point:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,365 @@
+//! The geographic graticule: meridians and parallels of the unrotated world, so that north can
+//! still be found once the view is oblique.
+//!
+//! The lines are sampled every degree and drawn like the routes, through the projection and the
+//! view rotation, so they bend and break exactly as the map does.  Where the map is zoomed in far
+//! enough for a degree to show as a visible chord, [`refine`] fills in more samples.
+
+use crate::coords::LonLat;
+use egui::text::Fonts;
+use egui::{Align2, Color32, FontId, Pos2, Rect, Shape, Stroke, Ui, Vec2};
+
+/// the tilt of the Earth's axis, which puts the tropics and the polar circles where they are
+pub const OBLIQUITY_DEGREES: f32 = 23.436;
+
+/// degrees between the samples along a line
+const SAMPLE_DEGREES: f32 = 1.0;
+
+/// the most samples [`refine`] puts in for one, enough for the deepest zoom
+const MAX_SUBDIVISIONS: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineKind {
+    Meridian,
+    Parallel,
+    Equator,
+    PrimeMeridian,
+    /// the tropics of Cancer and Capricorn
+    Tropic,
+    /// the Arctic and Antarctic circles
+    PolarCircle,
+}
+
+impl LineKind {
+    pub fn stroke(&self) -> Stroke {
+        match self {
+            LineKind::Meridian | LineKind::Parallel => {
+                Stroke::new(0.75, Color32::from_white_alpha(110))
+            }
+            LineKind::Equator | LineKind::PrimeMeridian => Stroke::new(1.5, Color32::WHITE),
+            LineKind::Tropic => Stroke::new(1.0, Color32::from_rgb(0x90, 0xff, 0x90)),
+            LineKind::PolarCircle => Stroke::new(1.0, Color32::from_rgb(0xa0, 0xc8, 0xff)),
+        }
+    }
+
+    /// the tropics and polar circles are dashed, to tell them from the regular parallels
+    pub fn dashed(&self) -> bool {
+        matches!(self, LineKind::Tropic | LineKind::PolarCircle)
+    }
+}
+
+pub struct GraticuleLine {
+    pub kind: LineKind,
+    pub label: String,
+    /// from pole to pole for a meridian, and from -180° to 180° for a parallel
+    pub points: Vec<LonLat>,
+}
+
+impl GraticuleLine {
+    fn meridian(longitude: f32, kind: LineKind, label: String) -> Self {
+        let count = (180.0 / SAMPLE_DEGREES) as usize;
+        let points = (0..=count)
+            .map(|i| LonLat::from_degrees(longitude, i as f32 * SAMPLE_DEGREES - 90.0))
+            .collect();
+        Self {
+            kind,
+            label,
+            points,
+        }
+    }
+
+    fn parallel(latitude: f32, kind: LineKind, label: String) -> Self {
+        let count = (360.0 / SAMPLE_DEGREES) as usize;
+        let points = (0..=count)
+            .map(|i| LonLat::from_degrees(i as f32 * SAMPLE_DEGREES - 180.0, latitude))
+            .collect();
+        Self {
+            kind,
+            label,
+            points,
+        }
+    }
+}
+
+/// the meridians and parallels every `spacing` degrees, then the equator, prime meridian,
+/// tropics and polar circles, so those are drawn on top
+pub fn graticule(spacing: u32) -> Vec<GraticuleLine> {
+    let spacing = spacing.max(1) as i32;
+    let mut lines = vec![];
+    // every multiple of the spacing east of -180°, which is drawn as 180°
+    for longitude in (-179..=180).filter(|l| l % spacing == 0 && *l != 0) {
+        let label = match longitude {
+            180 => "180°".to_owned(),
+            l if l > 0 => format!("{l}°E"),
+            l => format!("{}°W", -l),
+        };
+        lines.push(GraticuleLine::meridian(
+            longitude as f32,
+            LineKind::Meridian,
+            label,
+        ));
+    }
+    for latitude in (-89..=89).filter(|l| l % spacing == 0 && *l != 0) {
+        let label = if latitude > 0 {
+            format!("{latitude}°N")
+        } else {
+            format!("{}°S", -latitude)
+        };
+        lines.push(GraticuleLine::parallel(
+            latitude as f32,
+            LineKind::Parallel,
+            label,
+        ));
+    }
+
+    let polar = 90.0 - OBLIQUITY_DEGREES;
+    for (latitude, kind, label) in [
+        (OBLIQUITY_DEGREES, LineKind::Tropic, "Tropic of Cancer"),
+        (-OBLIQUITY_DEGREES, LineKind::Tropic, "Tropic of Capricorn"),
+        (polar, LineKind::PolarCircle, "Arctic Circle"),
+        (-polar, LineKind::PolarCircle, "Antarctic Circle"),
+        (0.0, LineKind::Equator, "equator"),
+    ] {
+        lines.push(GraticuleLine::parallel(latitude, kind, label.to_owned()));
+    }
+    lines.push(GraticuleLine::meridian(
+        0.0,
+        LineKind::PrimeMeridian,
+        "prime meridian".to_owned(),
+    ));
+    lines
+}
+
+/// `points` with more samples wherever neighbours land more than `max_length` apart on screen,
+/// near enough to `visible` to be seen.  `to_screen` is `None` off the map.  Meridians and
+/// parallels are straight in longitude and latitude, so the samples are interpolated in those.
+pub fn refine(
+    points: &[LonLat],
+    to_screen: impl Fn(LonLat) -> Option<Pos2>,
+    visible: &Rect,
+    max_length: f32,
+) -> Vec<LonLat> {
+    let near = visible.expand(max_length);
+    let mut refined = Vec::with_capacity(points.len());
+    let mut previous: Option<(LonLat, Option<Pos2>)> = None;
+    for &point in points {
+        let screen = to_screen(point);
+        if let Some((last, Some(last_screen))) = previous {
+            if let Some(screen) = screen {
+                let length = last_screen.distance(screen);
+                if length > max_length && Rect::from_two_pos(last_screen, screen).intersects(near) {
+                    let count = ((length / max_length).ceil() as usize).min(MAX_SUBDIVISIONS);
+                    refined.extend((1..count).map(|i| {
+                        let t = i as f32 / count as f32;
+                        LonLat::new(
+                            last.longitude + (point.longitude - last.longitude) * t,
+                            last.latitude + (point.latitude - last.latitude) * t,
+                        )
+                    }));
+                }
+            }
+        }
+        refined.push(point);
+        previous = Some((point, screen));
+    }
+    refined
+}
+
+/// where a line drawn as `segments` meets the edge of the map or of `visible`: the ends of its
+/// runs inside `visible`, or where the chord to the point outside crosses its edge.
+/// `line_ends`, where the line itself starts and stops, are left out since those are in the
+/// middle of the map.
+pub fn edge_points(segments: &[Vec<Pos2>], visible: &Rect, line_ends: &[Pos2]) -> Vec<Pos2> {
+    let mut points = vec![];
+    for segment in segments {
+        let inside = |i: usize| visible.contains(segment[i]);
+        for i in (0..segment.len()).filter(|i| inside(*i)) {
+            let starts_run = i == 0 || !inside(i - 1);
+            let ends_run = i + 1 == segment.len() || !inside(i + 1);
+            // a run of a single point is just the line grazing the edge
+            if starts_run == ends_run || line_ends.contains(&segment[i]) {
+                continue;
+            }
+            let outside = if starts_run {
+                i.checked_sub(1)
+            } else {
+                Some(i + 1).filter(|j| *j < segment.len())
+            };
+            points.push(match outside {
+                Some(j) => entry_point(segment[j], segment[i], visible),
+                // the run meets the edge of the map
+                None => segment[i],
+            });
+        }
+    }
+    points
+}
+
+/// where the chord from `outside` to `inside` enters `rect`, which holds `inside`
+fn entry_point(outside: Pos2, inside: Pos2, rect: &Rect) -> Pos2 {
+    let along = |from: f32, to: f32, min: f32, max: f32| {
+        if from < min {
+            (min - from) / (to - from)
+        } else if from > max {
+            (max - from) / (to - from)
+        } else {
+            0.0
+        }
+    };
+    let t = along(outside.x, inside.x, rect.left(), rect.right()).max(along(
+        outside.y,
+        inside.y,
+        rect.top(),
+        rect.bottom(),
+    ));
+    outside + (inside - outside) * t
+}
+
+/// a label at `pos` on the edge of `visible`, on a dark backing so it reads over the imagery,
+/// and placed on the inside of whichever edge is nearest
+pub fn label_shapes(
+    fonts: &Fonts,
+    text: &str,
+    pos: Pos2,
+    visible: &Rect,
+    color: Color32,
+) -> [Shape; 2] {
+    let edges = [
+        (
+            pos.x - visible.left(),
+            Align2::LEFT_CENTER,
+            Vec2::new(3.0, 0.0),
+        ),
+        (
+            visible.right() - pos.x,
+            Align2::RIGHT_CENTER,
+            Vec2::new(-3.0, 0.0),
+        ),
+        (
+            pos.y - visible.top(),
+            Align2::CENTER_TOP,
+            Vec2::new(0.0, 3.0),
+        ),
+        (
+            visible.bottom() - pos.y,
+            Align2::CENTER_BOTTOM,
+            Vec2::new(0.0, -3.0),
+        ),
+    ];
+    let (_, anchor, inwards) = edges
+        .into_iter()
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+        .expect("four edges");
+    let text = Shape::text(
+        fonts,
+        pos + inwards,
+        anchor,
+        text,
+        FontId::proportional(10.0),
+        color,
+    );
+    let backing = Shape::rect_filled(
+        text.visual_bounding_rect().expand(1.5),
+        2.0,
+        Color32::from_black_alpha(140),
+    );
+    [backing, text]
+}
+
+/// the graticule settings of a map widget
+pub(crate) struct GraticuleOptions {
+    pub show: bool,
+    /// degrees between the regular meridians and parallels
+    spacing: u32,
+    /// `graticule(spacing)`, which only changes with the spacing
+    lines: Vec<GraticuleLine>,
+}
+
+impl Default for GraticuleOptions {
+    fn default() -> Self {
+        let spacing = 15;
+        Self {
+            show: false,
+            spacing,
+            lines: graticule(spacing),
+        }
+    }
+}
+
+impl GraticuleOptions {
+    pub fn lines(&self) -> &[GraticuleLine] {
+        if self.show {
+            &self.lines
+        } else {
+            &[]
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut Ui) {
+        ui.checkbox(&mut self.show, "graticule")
+            .on_hover_text("draw the meridians and parallels of the unrotated world");
+        if self.show {
+            let changed = ui
+                .add(
+                    egui::DragValue::new(&mut self.spacing)
+                        .speed(0.2)
+                        .clamp_range(1..=90)
+                        .suffix("° grid"),
+                )
+                .changed();
+            if changed {
+                self.lines = graticule(self.spacing);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_sit_where_chords_cross_the_edge() {
+        let visible = Rect::from_min_max(Pos2::new(0.0, 0.0), Pos2::new(100.0, 100.0));
+        let segment = vec![
+            Pos2::new(-20.0, 40.0),
+            Pos2::new(20.0, 60.0),
+            Pos2::new(80.0, 60.0),
+            Pos2::new(110.0, 130.0),
+        ];
+        let points = edge_points(&[segment], &visible, &[]);
+        assert_eq!(points.len(), 2);
+        assert!((points[0] - Pos2::new(0.0, 50.0)).length() < 1e-4);
+        assert!((points[1] - Pos2::new(80.0 + 30.0 * 4.0 / 7.0, 100.0)).length() < 1e-4);
+    }
+
+    #[test]
+    fn meridians_are_symmetric_about_the_prime_meridian() {
+        let meridians = |spacing| -> Vec<i32> {
+            graticule(spacing)
+                .iter()
+                .filter(|line| line.kind == LineKind::Meridian)
+                .map(|line| line.points[0].longitude.to_degrees().round() as i32)
+                .collect()
+        };
+        // 40° does not divide 180°, so the westernmost meridian is 160°W and not 180°
+        assert_eq!(meridians(40), [-160, -120, -80, -40, 40, 80, 120, 160]);
+        for spacing in [7, 15, 30, 50, 90, 180] {
+            let longitudes = meridians(spacing);
+            let east = longitudes.iter().filter(|l| **l > 0 && **l < 180).count();
+            let west = longitudes.iter().filter(|l| **l < 0).count();
+            assert_eq!(east, west, "every {spacing}°: {longitudes:?}");
+            for longitude in longitudes.iter().filter(|l| l.abs() != 180) {
+                assert!(
+                    longitudes.contains(&-longitude),
+                    "every {spacing}°: {longitudes:?}"
+                );
+            }
+            assert_eq!(
+                longitudes.contains(&180),
+                180 % spacing == 0,
+                "every {spacing}°: {longitudes:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,229 @@
+//! A stack of equirectangular images drawn one over another, such as a base map, a bathymetry
+//! tint and clouds or night lights.
+//!
+//! fragment.glsl composites the visible layers bottom first, each through the same rotation,
+//! and [`composite`] does the same for the CPU map.
+
+use crate::coords::LonLat;
+use crate::raw_image::RawImage;
+use crate::world_map::WorldSampler;
+use egui::Ui;
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+
+/// how many layers the shader can composite.  Must match MAX_LAYERS in fragment.glsl.
+pub const MAX_LAYERS: usize = 4;
+
+/// how the color of a layer is combined with what is below it, before the opacity is applied
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum BlendMode {
+    /// the layer's own color
+    #[default]
+    Normal,
+    /// darkens, as for a tint or shading
+    Multiply,
+    /// lightens, as for clouds
+    Screen,
+    /// adds light, as for night lights
+    Add,
+}
+
+impl BlendMode {
+    pub const ALL: [BlendMode; 4] = [
+        BlendMode::Normal,
+        BlendMode::Multiply,
+        BlendMode::Screen,
+        BlendMode::Add,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BlendMode::Normal => "normal",
+            BlendMode::Multiply => "multiply",
+            BlendMode::Screen => "screen",
+            BlendMode::Add => "add",
+        }
+    }
+
+    /// must match the BLEND_ constants in fragment.glsl
+    pub fn shader_index(&self) -> i32 {
+        match self {
+            BlendMode::Normal => 0,
+            BlendMode::Multiply => 1,
+            BlendMode::Screen => 2,
+            BlendMode::Add => 3,
+        }
+    }
+
+    /// `above` blended onto `below`, in fractions.  Must match blend() in fragment.glsl.
+    pub fn blend(&self, below: [f32; 3], above: [f32; 3]) -> [f32; 3] {
+        [0, 1, 2].map(|i| {
+            let (below, above) = (below[i], above[i]);
+            match self {
+                BlendMode::Normal => above,
+                BlendMode::Multiply => below * above,
+                BlendMode::Screen => 1.0 - (1.0 - below) * (1.0 - above),
+                BlendMode::Add => (below + above).min(1.0),
+            }
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct ImageLayer {
+    pub name: String,
+    pub sampler: Arc<WorldSampler>,
+    pub visible: bool,
+    /// 0 is invisible and 1 is as opaque as the image's own alpha
+    pub opacity: f32,
+    pub blend: BlendMode,
+}
+
+impl ImageLayer {
+    pub fn new(name: String, sampler: Arc<WorldSampler>) -> Self {
+        Self {
+            name,
+            sampler,
+            visible: true,
+            opacity: 1.0,
+            blend: BlendMode::default(),
+        }
+    }
+
+    /// whether it makes any difference to the map
+    pub fn shown(&self) -> bool {
+        self.visible && self.opacity > 0.0
+    }
+}
+
+/// the color of the map at `position`, from `layers` bottom first over black.  `neighbours` are
+/// the positions one output pixel away, which tell how much of each image the pixel covers.
+pub fn composite(layers: &[ImageLayer], position: LonLat, neighbours: &[LonLat]) -> [u8; 4] {
+    let mut color = [0.0; 3];
+    for layer in layers.iter().filter(|layer| layer.shown()) {
+        let footprint = neighbours
+            .iter()
+            .map(|neighbour| layer.sampler.footprint(position, *neighbour))
+            .fold(1.0, f32::max);
+        let [r, g, b, alpha] = layer.sampler.get(position, footprint);
+        if alpha <= 0.0 {
+            continue;
+        }
+        // filtered premultiplied, but blended straight
+        let blended = layer
+            .blend
+            .blend(color, [r, g, b].map(|c| (c / alpha).min(1.0)));
+        let t = alpha * layer.opacity;
+        color = [0, 1, 2].map(|i| color[i] + (blended[i] - color[i]) * t);
+    }
+    let [r, g, b] = color.map(|c| (c * 255.0).round() as u8);
+    [r, g, b, 0xff]
+}
+
+/// the layers of a map widget and the "layers" menu that edits them
+pub(crate) struct LayerStack {
+    /// bottom first
+    pub layers: Vec<ImageLayer>,
+    /// the PNG to add as the next layer
+    path: String,
+    /// why the last PNG could not be added, or a layer could not be uploaded to the GPU
+    pub error: Option<String>,
+}
+
+impl Default for LayerStack {
+    fn default() -> Self {
+        Self {
+            layers: vec![ImageLayer::new(
+                "world".to_owned(),
+                Arc::new(WorldSampler::new()),
+            )],
+            path: String::new(),
+            error: None,
+        }
+    }
+}
+
+impl LayerStack {
+    /// add an equirectangular PNG as the top layer
+    pub fn add_png(&mut self, path: &str) -> Result<(), String> {
+        if self.layers.len() >= MAX_LAYERS {
+            return Err(format!("at most {MAX_LAYERS} layers"));
+        }
+        let file = File::open(path).map_err(|error| format!("{path}: {error}"))?;
+        let image =
+            RawImage::from_png(BufReader::new(file)).map_err(|error| format!("{path}: {error}"))?;
+        let name = std::path::Path::new(path)
+            .file_stem()
+            .map_or(path.to_owned(), |stem| stem.to_string_lossy().into_owned());
+        self.layers.push(ImageLayer::new(
+            name,
+            Arc::new(WorldSampler::from_image(image)),
+        ));
+        Ok(())
+    }
+
+    /// returns true if anything about the layers changed
+    pub fn ui(&mut self, ui: &mut Ui) -> bool {
+        let mut changed = false;
+        let mut swap = None;
+        let mut remove = None;
+        let count = self.layers.len();
+        // top first, the way they are stacked
+        for (i, layer) in self.layers.iter_mut().enumerate().rev() {
+            ui.horizontal(|ui| {
+                changed |= ui.checkbox(&mut layer.visible, &layer.name).changed();
+                changed |= ui
+                    .add(egui::Slider::new(&mut layer.opacity, 0.0..=1.0).text("opacity"))
+                    .changed();
+                egui::ComboBox::from_id_source(("blend", i))
+                    .selected_text(layer.blend.name())
+                    .show_ui(ui, |ui| {
+                        for candidate in BlendMode::ALL {
+                            changed |= ui
+                                .selectable_value(&mut layer.blend, candidate, candidate.name())
+                                .changed();
+                        }
+                    });
+                if ui
+                    .add_enabled(i + 1 < count, egui::Button::new("up"))
+                    .clicked()
+                {
+                    swap = Some(i);
+                }
+                if ui.add_enabled(i > 0, egui::Button::new("down")).clicked() {
+                    swap = Some(i - 1);
+                }
+                if ui.button("remove").clicked() {
+                    remove = Some(i);
+                }
+            });
+        }
+        if let Some(i) = swap {
+            self.layers.swap(i, i + 1);
+            changed = true;
+        }
+        if let Some(i) = remove {
+            self.layers.remove(i);
+            changed = true;
+        }
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.path);
+            if ui
+                .add_enabled(count < MAX_LAYERS, egui::Button::new("add layer"))
+                .on_hover_text("an equirectangular PNG, transparent where its alpha is")
+                .clicked()
+            {
+                let path = self.path.clone();
+                self.error = self.add_png(&path).err();
+                changed |= self.error.is_none();
+            }
+        });
+        if let Some(error) = &self.error {
+            ui.colored_label(ui.visuals().error_fg_color, error);
+        }
+        changed
+    }
+}
@@ -0,0 +1,150 @@
+//! How the projected map is placed in a widget of any shape.
+//!
+//! Every projection is drawn on a plane 2π wide and π high (see [`crate::projection`]), so the
+//! map is 2:1 whatever the widget is.  The map widgets work out the rect the whole map would
+//! cover with [`MapLayout::map_rect`] and take (u,v) fractions of that rect, not of the widget,
+//! for drawing and for hit-testing alike.
+
+use egui::{Pos2, Rect, Ui, Vec2};
+use serde::{Deserialize, Serialize};
+
+/// width over height of the undistorted map
+pub const MAP_ASPECT: f32 = 2.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum MapLayout {
+    /// the whole map at 2:1, with bars along two sides of the widget
+    #[default]
+    Fit,
+    /// the widget covered at 2:1, with the map cut off along two sides
+    Fill,
+    /// the whole map stretched over the whole widget
+    Stretch,
+}
+
+impl MapLayout {
+    pub const ALL: [MapLayout; 3] = [MapLayout::Fit, MapLayout::Fill, MapLayout::Stretch];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MapLayout::Fit => "fit",
+            MapLayout::Fill => "fill",
+            MapLayout::Stretch => "stretch",
+        }
+    }
+
+    /// the rect that the whole map covers when laid out in `widget`.  It is centered on the
+    /// widget, and for [`MapLayout::Fill`] it sticks out past two of its edges.
+    pub fn map_rect(&self, widget: Rect) -> Rect {
+        let width_for_height = widget.height() * MAP_ASPECT;
+        let wide = widget.width() > width_for_height;
+        let size = match self {
+            MapLayout::Stretch => return widget,
+            // the widget is wider than the map, so the height limits it
+            MapLayout::Fit if wide => Vec2::new(width_for_height, widget.height()),
+            MapLayout::Fill if !wide => Vec2::new(width_for_height, widget.height()),
+            MapLayout::Fit | MapLayout::Fill => {
+                Vec2::new(widget.width(), widget.width() / MAP_ASPECT)
+            }
+        };
+        Rect::from_center_size(widget.center(), size)
+    }
+}
+
+/// the (u,v) fraction of `map_rect` at `pos`, or `None` if it is off the map, as over the bars
+/// of [`MapLayout::Fit`]
+pub fn map_fraction(map_rect: &Rect, pos: Pos2) -> Option<Vec2> {
+    map_rect
+        .contains(pos)
+        .then(|| (pos - map_rect.min) / map_rect.size())
+}
+
+pub fn layout_combo_box(ui: &mut Ui, layout: &mut MapLayout) {
+    egui::ComboBox::from_id_source("layout")
+        .selected_text(layout.name())
+        .show_ui(ui, |ui| {
+            for candidate in MapLayout::ALL {
+                ui.selectable_value(layout, candidate, candidate.name());
+            }
+        })
+        .response
+        .on_hover_text("how the 2:1 map fits a window of another shape");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn widget(width: f32, height: f32) -> Rect {
+        Rect::from_min_size(Pos2::new(10.0, 20.0), Vec2::new(width, height))
+    }
+
+    #[test]
+    fn pillarbox_in_a_wide_widget() {
+        let wide = widget(400.0, 100.0);
+        let fit = MapLayout::Fit.map_rect(wide);
+        assert_eq!(
+            fit,
+            Rect::from_center_size(wide.center(), Vec2::new(200.0, 100.0))
+        );
+        assert!(wide.contains_rect(fit));
+        let fill = MapLayout::Fill.map_rect(wide);
+        assert_eq!(
+            fill,
+            Rect::from_center_size(wide.center(), Vec2::new(400.0, 200.0))
+        );
+        assert_eq!(MapLayout::Stretch.map_rect(wide), wide);
+    }
+
+    #[test]
+    fn letterbox_in_a_tall_widget() {
+        let tall = widget(200.0, 300.0);
+        let fit = MapLayout::Fit.map_rect(tall);
+        assert_eq!(
+            fit,
+            Rect::from_center_size(tall.center(), Vec2::new(200.0, 100.0))
+        );
+        assert!(tall.contains_rect(fit));
+        let fill = MapLayout::Fill.map_rect(tall);
+        assert_eq!(
+            fill,
+            Rect::from_center_size(tall.center(), Vec2::new(600.0, 300.0))
+        );
+        assert_eq!(MapLayout::Stretch.map_rect(tall), tall);
+    }
+
+    #[test]
+    fn every_layout_covers_a_two_to_one_widget() {
+        let exact = widget(300.0, 150.0);
+        for layout in MapLayout::ALL {
+            assert_eq!(layout.map_rect(exact), exact, "{}", layout.name());
+        }
+    }
+
+    /// the same storage calls that app.rs makes, kept in memory
+    #[derive(Default)]
+    struct MemoryStorage(HashMap<String, String>);
+
+    impl eframe::Storage for MemoryStorage {
+        fn get_string(&self, key: &str) -> Option<String> {
+            self.0.get(key).cloned()
+        }
+
+        fn set_string(&mut self, key: &str, value: String) {
+            self.0.insert(key.to_owned(), value);
+        }
+
+        fn flush(&mut self) {}
+    }
+
+    #[test]
+    fn layout_survives_a_restart() {
+        for layout in MapLayout::ALL {
+            let mut storage = MemoryStorage::default();
+            eframe::set_value(&mut storage, "layout", &layout);
+            let restored: Option<MapLayout> = eframe::get_value(&storage, "layout");
+            assert_eq!(restored, Some(layout));
+        }
+    }
+}
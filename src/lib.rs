@@ -1,9 +1,22 @@
 #![warn(clippy::all, rust_2018_idioms)]
 
+mod animation;
 mod app;
 mod background_image;
+pub mod coords;
+pub mod distortion;
+pub mod ellipsoid;
+mod export;
+pub mod geodesy;
+pub mod graticule;
+mod layers;
+pub mod layout;
+pub mod oblique;
+pub mod projection;
 mod raw_image;
-mod remapper;
+pub mod remapper;
+pub mod tissot;
+pub mod viewport;
 mod world2;
 mod world_map;
 mod world_map2;
@@ -0,0 +1,223 @@
+//! Describe a view rotation the way GIS tools do, as an oblique pole.
+//!
+//! The parameters follow the CF `rotated_latitude_longitude` convention and map onto PROJ's
+//! `ob_tran` like so: `+o_lat_p` is [`ObliquePole::pole_latitude`], `+o_lon_p` is
+//! [`ObliquePole::rotation`] and `+lon_0` is [`ObliquePole::pole_longitude`] plus 180°.
+//! The rotated frame is drawn with its north up, which is view `-z` (see [`Projection`]).
+//!
+//! Our cartesian frames have `z` pointing south (see [`crate::coords`]), so the world and view
+//! frames are both mirrored in `z` relative to the geographic frames that PROJ works with.
+
+use crate::ellipsoid::EARTH_MEAN_RADIUS_M;
+use crate::projection::Projection;
+use crate::remapper::Scalar;
+use cgmath::{Matrix, Matrix3, Rad, SquareMatrix, Vector3};
+use std::f64::consts::PI;
+use std::fmt;
+
+/// where the pole of the rotated frame sits, all in degrees
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ObliquePole {
+    /// geographic latitude of the north pole of the rotated frame
+    pub pole_latitude: f64,
+    /// geographic longitude of the north pole of the rotated frame
+    pub pole_longitude: f64,
+    /// longitude of the geographic north pole, measured in the rotated frame
+    pub rotation: f64,
+}
+
+/// why a PROJ string could not be turned into an [`ObliquePole`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ProjStringError {
+    /// only `+proj=ob_tran` describes a rotation
+    NotObTran,
+    /// a parameter we cannot do without
+    Missing(&'static str),
+    /// the key and the value that is not a plain number of degrees
+    BadNumber(String, String),
+}
+
+impl fmt::Display for ProjStringError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProjStringError::NotObTran => write!(f, "not a +proj=ob_tran string"),
+            ProjStringError::Missing(key) => write!(f, "missing +{key}"),
+            ProjStringError::BadNumber(key, value) => write!(f, "+{key}={value} is not a number"),
+        }
+    }
+}
+
+impl std::error::Error for ProjStringError {}
+
+impl ObliquePole {
+    /// describe the rotation of a view matrix, which maps the view frame to the world frame
+    pub fn from_matrix<S: Scalar>(matrix: Matrix3<S>) -> Self {
+        let m = geographic_to_rotated(matrix.cast::<f64>().unwrap());
+        let pole_latitude = m.z.z.clamp(-1.0, 1.0).asin();
+        let (pole_longitude, rotation) = if pole_latitude.cos() < 1e-9 {
+            // the frames share their poles, so only the difference of the longitudes matters
+            let spin = m * tilt(pole_latitude).transpose();
+            (PI, spin.x.y.atan2(spin.x.x))
+        } else {
+            (m.y.z.atan2(m.x.z), m.z.y.atan2(m.z.x))
+        };
+
+        Self {
+            pole_latitude: pole_latitude.to_degrees(),
+            pole_longitude: wrap_degrees(pole_longitude.to_degrees()),
+            rotation: wrap_degrees(rotation.to_degrees()),
+        }
+    }
+
+    /// the view matrix for this rotation, the inverse of [`Self::from_matrix`]
+    pub fn matrix<S: Scalar>(&self) -> Matrix3<S> {
+        let m = Matrix3::from_angle_z(Rad(self.rotation.to_radians()))
+            * tilt(self.pole_latitude.to_radians())
+            * Matrix3::from_angle_z(Rad(-(self.pole_longitude.to_radians() + PI)));
+        let matrix = mirror() * m.transpose() * mirror();
+        matrix.cast::<S>().unwrap()
+    }
+
+    /// the PROJ string that draws the same view as `projection`, up to scale
+    pub fn to_proj_string(&self, projection: Projection) -> String {
+        format!(
+            "+proj=ob_tran {} +o_lat_p={:.6} +o_lon_p={:.6} +lon_0={:.6} +R={} +no_defs",
+            projection.proj_parameters(),
+            self.pole_latitude,
+            self.rotation,
+            wrap_degrees(self.pole_longitude + 180.0),
+            EARTH_MEAN_RADIUS_M,
+        )
+    }
+
+    /// read the rotation from a PROJ `ob_tran` string, along with its `+o_proj` if we know it.
+    /// Angles must be plain decimal degrees.
+    pub fn from_proj_string(text: &str) -> Result<(Self, Option<Projection>), ProjStringError> {
+        let parameters: Vec<(&str, &str)> = text
+            .split_whitespace()
+            .map(|token| {
+                let token = token.trim_start_matches('+');
+                token.split_once('=').unwrap_or((token, ""))
+            })
+            .collect();
+        let find = |key: &str| {
+            parameters
+                .iter()
+                .find(|(candidate, _)| *candidate == key)
+                .map(|(_, value)| *value)
+        };
+        let degrees = |key: &'static str| -> Result<Option<f64>, ProjStringError> {
+            find(key)
+                .map(|value| {
+                    value
+                        .parse()
+                        .map_err(|_| ProjStringError::BadNumber(key.to_string(), value.to_string()))
+                })
+                .transpose()
+        };
+
+        if find("proj") != Some("ob_tran") {
+            return Err(ProjStringError::NotObTran);
+        }
+        let pole_latitude = degrees("o_lat_p")?.ok_or(ProjStringError::Missing("o_lat_p"))?;
+        let rotation = degrees("o_lon_p")?.unwrap_or(0.0);
+        let lon_0 = degrees("lon_0")?.unwrap_or(0.0);
+        let projection = find("o_proj").and_then(Projection::from_o_proj);
+
+        let pole = Self {
+            pole_latitude,
+            pole_longitude: wrap_degrees(lon_0 - 180.0),
+            rotation: wrap_degrees(rotation),
+        };
+        Ok((pole, projection))
+    }
+}
+
+impl fmt::Display for ObliquePole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "pole {:.4}°, {:.4}°, rotated {:.4}°",
+            self.pole_latitude, self.pole_longitude, self.rotation
+        )
+    }
+}
+
+/// the rotation from geographic cartesian coordinates to those of the rotated frame,
+/// `Rz(o_lon_p) · tilt(o_lat_p) · Rz(-lon_0)` in PROJ's terms
+fn geographic_to_rotated(matrix: Matrix3<f64>) -> Matrix3<f64> {
+    mirror() * matrix.transpose() * mirror()
+}
+
+/// the middle step of PROJ's `ob_tran`, which tips the pole down to `latitude`
+fn tilt<S: Scalar>(latitude: S) -> Matrix3<S> {
+    let (sin, cos) = latitude.sin_cos();
+    Matrix3::from_cols(
+        Vector3::new(sin, S::zero(), -cos),
+        Vector3::unit_y(),
+        Vector3::new(cos, S::zero(), sin),
+    )
+}
+
+/// swaps between our south-pointing `z` and the geographic north-pointing `z`
+fn mirror() -> Matrix3<f64> {
+    Matrix3::from_diagonal(Vector3::new(1.0, 1.0, -1.0))
+}
+
+fn wrap_degrees(degrees: f64) -> f64 {
+    let wrapped = (degrees + 180.0).rem_euclid(360.0) - 180.0;
+    // keep rounding noise from showing up as -0.000000
+    if wrapped.abs() < 1e-12 {
+        0.0
+    } else {
+        wrapped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coords::LonLat;
+    use crate::remapper::matrix_from_anchors;
+
+    fn assert_close(actual: Matrix3<f64>, expected: Matrix3<f64>, context: &str) {
+        let error = [
+            actual.x - expected.x,
+            actual.y - expected.y,
+            actual.z - expected.z,
+        ]
+        .iter()
+        .flat_map(|column| [column.x, column.y, column.z])
+        .fold(0.0, |max: f64, e| max.max(e.abs()));
+        assert!(error < 1e-9, "{context}: {actual:?} is not {expected:?}");
+    }
+
+    #[test]
+    fn matrix_round_trip() {
+        let mut anchors = vec![];
+        for latitude in [-90.0, -60.0, -12.5, 0.0, 33.3, 75.0, 90.0] {
+            for longitude in [-180.0, -100.0, -0.5, 0.0, 45.0, 135.0, 180.0] {
+                anchors.push(LonLat::from_degrees(longitude, latitude));
+            }
+        }
+        for pair in anchors.windows(2) {
+            let Ok(matrix) = matrix_from_anchors::<f64>(pair) else {
+                continue;
+            };
+            let pole = ObliquePole::from_matrix(matrix);
+            assert_close(pole.matrix(), matrix, &format!("{pair:?} as {pole:?}"));
+        }
+    }
+
+    #[test]
+    fn matrix_round_trip_with_shared_poles() {
+        for degrees in [-180.0, -90.0, 0.0, 30.0, 179.0] {
+            let spin = Matrix3::from_angle_z(Rad(f64::to_radians(degrees)));
+            // the same poles, and the poles swapped by a half turn about the x axis
+            for matrix in [spin, spin * Matrix3::from_angle_x(Rad(PI))] {
+                let pole = ObliquePole::from_matrix(matrix);
+                assert_close(pole.matrix(), matrix, &format!("{pole:?}"));
+            }
+        }
+    }
+}
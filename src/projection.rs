@@ -0,0 +1,652 @@
+use crate::remapper::{scalar, Scalar};
+use cgmath::{InnerSpace, Vector2, Vector3};
+use egui::Ui;
+use std::f64::consts::{FRAC_PI_2, PI, SQRT_2};
+
+/// How the rotated sphere is laid out on the widget.
+///
+/// Every projection works in the rotated frame, where the chosen great circle is the equator and
+/// the midpoint of the anchors is at `(1,0,0)`.  The widget covers the plane rectangle
+/// `[-π,π]×[-π/2,π/2]`, which is exactly the extent of the equirectangular layout.
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum Projection {
+    #[default]
+    Equirectangular,
+    /// Hotine-style oblique Mercator.  In the rotated frame this is just the normal Mercator.
+    Mercator,
+    /// equal-area, with an elliptical boundary
+    Mollweide,
+    /// equal-area, with an elliptical boundary
+    Hammer,
+    /// Lambert cylindrical equal-area, stretched to fill the widget.
+    /// That puts the standard parallels at ±37.07°.
+    LambertCylindrical,
+    /// true distance and bearing from the first anchor, out to its antipode
+    AzimuthalEquidistant,
+    /// a globe seen from far away, tilted so the great circle shows up as a ring
+    Orthographic,
+    /// every great circle is a straight line.  Only the hemisphere around the anchor midpoint can
+    /// be shown, and it is cut off at [`GNOMONIC_LIMIT`] from the center.
+    Gnomonic,
+    /// conformal, centered on the first anchor.  With a wide field of view this is the
+    /// "little planet" rendering of a panorama.
+    Stereographic {
+        /// angle (radians) from the top edge of the widget to the bottom edge, through the center
+        field_of_view: f32,
+    },
+}
+
+impl Projection {
+    pub const ALL: [Projection; 9] = [
+        Projection::Equirectangular,
+        Projection::Mercator,
+        Projection::Mollweide,
+        Projection::Hammer,
+        Projection::LambertCylindrical,
+        Projection::AzimuthalEquidistant,
+        Projection::Orthographic,
+        Projection::Gnomonic,
+        Projection::Stereographic {
+            field_of_view: 120.0 * std::f32::consts::PI / 180.0,
+        },
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Projection::Equirectangular => "Equirectangular",
+            Projection::Mercator => "Oblique Mercator",
+            Projection::Mollweide => "Mollweide",
+            Projection::Hammer => "Hammer",
+            Projection::LambertCylindrical => "Lambert cylindrical equal-area",
+            Projection::AzimuthalEquidistant => "Azimuthal equidistant",
+            Projection::Orthographic => "Globe",
+            Projection::Gnomonic => "Gnomonic",
+            Projection::Stereographic { .. } => "Stereographic",
+        }
+    }
+
+    /// must match the `projection` uniform switch in fragment.glsl
+    pub fn shader_index(&self) -> i32 {
+        match self {
+            Projection::Equirectangular => 0,
+            Projection::Mercator => 1,
+            Projection::Mollweide => 2,
+            Projection::Hammer => 3,
+            Projection::LambertCylindrical => 4,
+            Projection::AzimuthalEquidistant => 5,
+            Projection::Orthographic => 6,
+            Projection::Gnomonic => 7,
+            Projection::Stereographic { .. } => 8,
+        }
+    }
+
+    /// for the `field_of_view` uniform in fragment.glsl
+    pub fn field_of_view(&self) -> Option<f32> {
+        match self {
+            Projection::Stereographic { field_of_view } => Some(*field_of_view),
+            _ => None,
+        }
+    }
+
+    /// the PROJ projection that draws the rotated frame the same way (up to scale),
+    /// for use as the `+o_proj` of an `ob_tran`.  See [`crate::oblique`]
+    pub fn proj_parameters(&self) -> String {
+        match self {
+            Projection::Equirectangular => "+o_proj=eqc".to_string(),
+            Projection::Mercator => "+o_proj=merc".to_string(),
+            Projection::Mollweide => "+o_proj=moll".to_string(),
+            Projection::Hammer => "+o_proj=hammer".to_string(),
+            Projection::LambertCylindrical => "+o_proj=cea".to_string(),
+            Projection::AzimuthalEquidistant => "+o_proj=aeqd".to_string(),
+            // the globe is seen from above the great circle
+            Projection::Orthographic => format!("+o_proj=ortho +lat_0={}", GLOBE_TILT.to_degrees()),
+            Projection::Gnomonic => "+o_proj=gnom".to_string(),
+            Projection::Stereographic { .. } => "+o_proj=stere".to_string(),
+        }
+    }
+
+    /// the inverse of [`Self::proj_parameters`], given the value of `+o_proj`
+    pub fn from_o_proj(o_proj: &str) -> Option<Projection> {
+        Projection::ALL.into_iter().find(|candidate| {
+            candidate.proj_parameters().split_whitespace().next()
+                == Some(format!("+o_proj={o_proj}").as_str())
+        })
+    }
+
+    /// azimuthal projections are centered on the first anchor instead of the anchor midpoint.
+    /// See [`GreatCircleRemapper::roll_to_center`](crate::remapper::GreatCircleRemapper::roll_to_center)
+    pub fn centered_on_first_anchor(&self) -> bool {
+        matches!(
+            self,
+            Projection::AzimuthalEquidistant | Projection::Stereographic { .. }
+        )
+    }
+
+    /// map a unit vector in the rotated frame to the (u,v) fraction of the widget.
+    /// Returns `None` if the point does not appear on the map.
+    pub fn project<S: Scalar>(&self, xyz: Vector3<S>) -> Option<Vector2<S>> {
+        let longitude = S::atan2(xyz.y, xyz.x);
+        let latitude = clamp_unit(xyz.z).asin();
+        let plane = match self {
+            Projection::Equirectangular => Vector2::new(longitude, latitude),
+            Projection::Mercator => Vector2::new(
+                longitude,
+                (S::FRAC_PI_4() + latitude * scalar(0.5)).tan().ln(),
+            ),
+            Projection::Mollweide => mollweide(longitude, latitude),
+            Projection::Hammer => hammer(longitude, latitude),
+            Projection::LambertCylindrical => {
+                Vector2::new(longitude, latitude.sin() * S::FRAC_PI_2())
+            }
+            Projection::AzimuthalEquidistant => azimuthal_equidistant(xyz),
+            Projection::Orthographic => orthographic(xyz)?,
+            Projection::Gnomonic => gnomonic(xyz)?,
+            Projection::Stereographic { field_of_view } => {
+                stereographic(xyz, scalar(*field_of_view as f64))?
+            }
+        };
+        plane_to_frac(plane)
+    }
+
+    /// map a (u,v) fraction of the widget to a unit vector in the rotated frame.
+    /// Returns `None` if the point is off the map.
+    pub fn unproject<S: Scalar>(&self, u: S, v: S) -> Option<Vector3<S>> {
+        let Vector2 { x, y } = frac_to_plane(u, v);
+        let (longitude, latitude) = match self {
+            Projection::Equirectangular => (x, y),
+            Projection::Mercator => (x, scalar::<S>(2.0) * y.exp().atan() - S::FRAC_PI_2()),
+            Projection::Mollweide => inverse_mollweide(x, y)?,
+            Projection::Hammer => inverse_hammer(x, y)?,
+            Projection::LambertCylindrical => (x, clamp_unit(y / S::FRAC_PI_2()).asin()),
+            Projection::AzimuthalEquidistant => return inverse_azimuthal_equidistant(x, y),
+            Projection::Orthographic => return inverse_orthographic(x, y),
+            Projection::Gnomonic => return inverse_gnomonic(x, y),
+            Projection::Stereographic { field_of_view } => {
+                return inverse_stereographic(x, y, scalar(*field_of_view as f64))
+            }
+        };
+        Some(longitude_latitude_to_cartesian(longitude, latitude))
+    }
+}
+
+pub fn projection_combo_box(ui: &mut Ui, projection: &mut Projection) {
+    egui::ComboBox::from_label("projection")
+        .selected_text(projection.name())
+        .show_ui(ui, |ui| {
+            for candidate in Projection::ALL {
+                let selected = projection.shader_index() == candidate.shader_index();
+                if ui.selectable_label(selected, candidate.name()).clicked() && !selected {
+                    *projection = candidate;
+                }
+            }
+        });
+
+    if let Projection::Stereographic { field_of_view } = projection {
+        let mut degrees = field_of_view.to_degrees();
+        ui.add(
+            egui::Slider::new(&mut degrees, 30.0..=350.0)
+                .text("field of view")
+                .suffix("°"),
+        );
+        *field_of_view = degrees.to_radians();
+    }
+}
+
+fn frac_to_plane<S: Scalar>(u: S, v: S) -> Vector2<S> {
+    let half: S = scalar(0.5);
+    Vector2::new((u - half) * S::TAU(), (v - half) * S::PI())
+}
+
+fn plane_to_frac<S: Scalar>(plane: Vector2<S>) -> Option<Vector2<S>> {
+    let half: S = scalar(0.5);
+    let u = plane.x / S::TAU() + half;
+    let v = plane.y / S::PI() + half;
+    let inside = |t: S| t >= S::zero() && t <= S::one();
+    if inside(u) && inside(v) {
+        Some(Vector2::new(u, v))
+    } else {
+        None
+    }
+}
+
+pub(crate) fn clamp_unit<S: Scalar>(t: S) -> S {
+    t.max(-S::one()).min(S::one())
+}
+
+/// The Mollweide and Hammer ellipses are `4√2×2√2` in their native units.
+/// This scales them to fill the widget.
+const ELLIPSE_SCALE: f64 = PI / (2.0 * SQRT_2);
+
+fn mollweide<S: Scalar>(longitude: S, latitude: S) -> Vector2<S> {
+    let two: S = scalar(2.0);
+    // solve 2θ + sin 2θ = π sin φ for the auxiliary angle θ.  The left side increases
+    // monotonically, but flattens out at the poles, where Newton's method crawls or overshoots,
+    // so the root is kept bracketed and a step that leaves the bracket bisects instead.
+    let target = S::PI() * latitude.sin();
+    let (mut low, mut high) = (-S::FRAC_PI_2(), S::FRAC_PI_2());
+    let mut theta = latitude;
+    for _ in 0..100 {
+        let error = two * theta + (two * theta).sin() - target;
+        if error == S::zero() {
+            break;
+        }
+        if error < S::zero() {
+            low = theta;
+        } else {
+            high = theta;
+        }
+        let next = theta - error / (two + two * (two * theta).cos());
+        let next = if next > low && next < high {
+            next
+        } else {
+            (low + high) / two
+        };
+        if (next - theta).abs() <= S::epsilon() * theta.abs().max(S::one()) {
+            theta = next;
+            break;
+        }
+        theta = next;
+    }
+    Vector2::new(
+        two * S::SQRT_2() / S::PI() * longitude * theta.cos(),
+        S::SQRT_2() * theta.sin(),
+    ) * scalar(ELLIPSE_SCALE)
+}
+
+fn inverse_mollweide<S: Scalar>(x: S, y: S) -> Option<(S, S)> {
+    let two: S = scalar(2.0);
+    let scale: S = scalar(ELLIPSE_SCALE);
+    let (x, y) = (x / scale, y / scale);
+    let sin_theta = y / S::SQRT_2();
+    if sin_theta.abs() > S::one() {
+        return None;
+    }
+    let theta = sin_theta.asin();
+    // the half width of the ellipse at this height, with some slack for rounding, which near the
+    // poles would otherwise throw points on the edge off the map
+    let half_width = two * S::SQRT_2() * theta.cos();
+    if x.abs() > half_width + scalar(1e-12) {
+        return None;
+    }
+    let longitude = if half_width > S::zero() {
+        (S::PI() * x / half_width).max(-S::PI()).min(S::PI())
+    } else {
+        S::zero()
+    };
+    let latitude = clamp_unit((two * theta + (two * theta).sin()) / S::PI()).asin();
+    Some((longitude, latitude))
+}
+
+fn hammer<S: Scalar>(longitude: S, latitude: S) -> Vector2<S> {
+    let two: S = scalar(2.0);
+    let half_longitude = longitude / two;
+    let z = (S::one() + latitude.cos() * half_longitude.cos()).sqrt();
+    Vector2::new(
+        two * S::SQRT_2() * latitude.cos() * half_longitude.sin() / z,
+        S::SQRT_2() * latitude.sin() / z,
+    ) * scalar(ELLIPSE_SCALE)
+}
+
+fn inverse_hammer<S: Scalar>(x: S, y: S) -> Option<(S, S)> {
+    let two: S = scalar(2.0);
+    let scale: S = scalar(ELLIPSE_SCALE);
+    let (x, y) = (x / scale, y / scale);
+    // with some slack for rounding, which would otherwise throw points on the edge off the map
+    if x * x / scalar(8.0) + y * y / two > S::one() + scalar(1e-12) {
+        return None;
+    }
+    let z = (S::one() - (x / scalar(4.0)).powi(2) - (y / two).powi(2))
+        .max(S::zero())
+        .sqrt();
+    let longitude = two * S::atan2(z * x, two * (two * z * z - S::one()));
+    let latitude = clamp_unit(z * y).asin();
+    Some((longitude, latitude))
+}
+
+/// The whole sphere is a disk of radius π.  This fits it to the height of the widget.
+const AZIMUTHAL_EQUIDISTANT_SCALE: f64 = 0.5;
+
+/// The center is `(1,0,0)`; the rotated frame's equator runs horizontally through it.
+fn azimuthal_equidistant<S: Scalar>(xyz: Vector3<S>) -> Vector2<S> {
+    let c = clamp_unit(xyz.x).acos();
+    let direction = Vector2::new(xyz.y, xyz.z);
+    let length = direction.magnitude();
+    if length < S::epsilon() {
+        // the center, or its antipode, which is the whole rim, so any point of it will do
+        return Vector2::new(c * scalar(AZIMUTHAL_EQUIDISTANT_SCALE), S::zero());
+    }
+    direction * (c * scalar(AZIMUTHAL_EQUIDISTANT_SCALE) / length)
+}
+
+fn inverse_azimuthal_equidistant<S: Scalar>(x: S, y: S) -> Option<Vector3<S>> {
+    let plane = Vector2::new(x, y) / scalar(AZIMUTHAL_EQUIDISTANT_SCALE);
+    let c = plane.magnitude();
+    if c > S::PI() {
+        return None;
+    }
+    if c < S::epsilon() {
+        return Some(Vector3::unit_x());
+    }
+    let direction = plane * (c.sin() / c);
+    Some(Vector3::new(c.cos(), direction.x, direction.y))
+}
+
+/// how far the globe is tipped towards the viewer, so the equator of the rotated frame is not
+/// seen edge-on.  Keep in sync with fragment.glsl
+const GLOBE_TILT: f64 = 25.0 * PI / 180.0;
+
+/// the globe fills the height of the widget
+const GLOBE_RADIUS: f64 = FRAC_PI_2;
+
+/// (direction towards the viewer, screen right, screen down) in the rotated frame
+fn globe_axes<S: Scalar>() -> (Vector3<S>, Vector3<S>, Vector3<S>) {
+    let (sin, cos) = scalar::<S>(GLOBE_TILT).sin_cos();
+    (
+        Vector3::new(cos, S::zero(), -sin),
+        Vector3::unit_y(),
+        Vector3::new(sin, S::zero(), cos),
+    )
+}
+
+/// `None` for the hemisphere facing away from the viewer
+fn orthographic<S: Scalar>(xyz: Vector3<S>) -> Option<Vector2<S>> {
+    let (towards, right, down) = globe_axes();
+    if xyz.dot(towards) < S::zero() {
+        return None;
+    }
+    Some(Vector2::new(xyz.dot(right), xyz.dot(down)) * scalar(GLOBE_RADIUS))
+}
+
+fn inverse_orthographic<S: Scalar>(x: S, y: S) -> Option<Vector3<S>> {
+    let plane = Vector2::new(x, y) / scalar(GLOBE_RADIUS);
+    let r2 = plane.magnitude2();
+    if r2 > S::one() {
+        return None;
+    }
+    let (towards, right, down) = globe_axes();
+    Some(towards * (S::one() - r2).sqrt() + right * plane.x + down * plane.y)
+}
+
+/// angular distance from the center where the gnomonic map stops.  Keep in sync with fragment.glsl
+pub const GNOMONIC_LIMIT: f64 = 70.0 * PI / 180.0;
+
+/// puts 60° from the center at the top and bottom edges of the widget
+const GNOMONIC_SCALE: f64 = FRAC_PI_2 / 1.732_050_807_568_877_2;
+
+/// `None` beyond [`GNOMONIC_LIMIT`], which also excludes the hemisphere facing away
+fn gnomonic<S: Scalar>(xyz: Vector3<S>) -> Option<Vector2<S>> {
+    if xyz.x < scalar(GNOMONIC_LIMIT.cos()) {
+        return None;
+    }
+    Some(Vector2::new(xyz.y, xyz.z) * (scalar::<S>(GNOMONIC_SCALE) / xyz.x))
+}
+
+fn inverse_gnomonic<S: Scalar>(x: S, y: S) -> Option<Vector3<S>> {
+    let plane = Vector2::new(x, y) / scalar(GNOMONIC_SCALE);
+    if plane.magnitude() > scalar(GNOMONIC_LIMIT.tan()) {
+        return None;
+    }
+    Some(Vector3::new(S::one(), plane.x, plane.y).normalize())
+}
+
+/// maps the angle from the center to the top edge of the widget onto the top edge
+fn stereographic_scale<S: Scalar>(field_of_view: S) -> S {
+    S::FRAC_PI_2() / (scalar::<S>(2.0) * (field_of_view / scalar(4.0)).tan())
+}
+
+/// `None` only for the antipode of the center, which is infinitely far away
+fn stereographic<S: Scalar>(xyz: Vector3<S>, field_of_view: S) -> Option<Vector2<S>> {
+    if xyz.x <= -S::one() + S::epsilon() {
+        return None;
+    }
+    let scale = scalar::<S>(2.0) * stereographic_scale(field_of_view) / (S::one() + xyz.x);
+    Some(Vector2::new(xyz.y, xyz.z) * scale)
+}
+
+fn inverse_stereographic<S: Scalar>(x: S, y: S, field_of_view: S) -> Option<Vector3<S>> {
+    let two: S = scalar(2.0);
+    let plane = Vector2::new(x, y) / (two * stereographic_scale(field_of_view));
+    let r2 = plane.magnitude2();
+    Some(Vector3::new(S::one() - r2, two * plane.x, two * plane.y) / (S::one() + r2))
+}
+
+/// longitude is measured from the +x axis towards +y, latitude towards +z
+fn longitude_latitude_to_cartesian<S: Scalar>(longitude: S, latitude: S) -> Vector3<S> {
+    let r = latitude.cos();
+    Vector3::new(longitude.cos() * r, longitude.sin() * r, latitude.sin())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::InnerSpace;
+
+    #[test]
+    fn unproject_project_round_trip() {
+        for projection in Projection::ALL {
+            for i in 0..40 {
+                for j in 0..40 {
+                    // off the grid lines, which can fall exactly on the edge of the map
+                    let (u, v) = ((i as f64 + 0.25) / 40.0, (j as f64 + 0.25) / 40.0);
+                    let Some(xyz) = projection.unproject(u, v) else {
+                        continue;
+                    };
+                    assert!((xyz.magnitude() - 1.0).abs() < 1e-12);
+                    let uv = projection
+                        .project(xyz)
+                        .unwrap_or_else(|| panic!("{}: ({u}, {v}) is lost", projection.name()));
+                    assert!(
+                        (uv - Vector2::new(u, v)).magnitude() < 1e-9,
+                        "{}: ({u}, {v}) came back as {uv:?}",
+                        projection.name()
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn project_unproject_round_trip() {
+        for projection in Projection::ALL {
+            for i in 0..=24 {
+                for j in 1..36 {
+                    let longitude = (i as f64 * 15.0 - 180.0).to_radians();
+                    // short of the poles, where Mercator runs off the map
+                    let latitude = (j as f64 * 5.0 - 90.0).to_radians();
+                    let xyz = longitude_latitude_to_cartesian(longitude, latitude);
+                    let Some(uv) = projection.project(xyz) else {
+                        continue;
+                    };
+                    let back = projection
+                        .unproject(uv.x, uv.y)
+                        .unwrap_or_else(|| panic!("{}: {uv:?} is off the map", projection.name()));
+                    // the antimeridian is both edges of the map
+                    let error = if i == 0 || i == 24 {
+                        (back - xyz).magnitude().min((back.y + xyz.y).abs())
+                    } else {
+                        (back - xyz).magnitude()
+                    };
+                    assert!(
+                        error < 1e-9,
+                        "{}: {xyz:?} came back as {back:?}",
+                        projection.name()
+                    );
+                }
+            }
+        }
+    }
+
+    /// the point of the plane that `projection` puts `longitude` and `latitude` (degrees) at
+    fn plane(projection: Projection, longitude: f64, latitude: f64) -> Option<Vector2<f64>> {
+        let xyz = longitude_latitude_to_cartesian(longitude.to_radians(), latitude.to_radians());
+        let uv = projection.project(xyz)?;
+        Some(frac_to_plane(uv.x, uv.y))
+    }
+
+    #[test]
+    fn mercator_is_conformal() {
+        for latitude in [-60.0, -20.0, 0.0, 30.0, 45.0, 60.0] {
+            let at = |longitude: f64, latitude: f64| {
+                plane(Projection::Mercator, longitude, latitude).unwrap()
+            };
+            let expected = (PI / 4.0 + f64::to_radians(latitude) / 2.0).tan().ln();
+            assert!((at(10.0, latitude).y - expected).abs() < 1e-12);
+            // the same stretch along the meridian as along the parallel
+            let step = 1e-4;
+            let east = (at(10.0 + step, latitude) - at(10.0 - step, latitude)).magnitude();
+            let north = (at(10.0, latitude + step) - at(10.0, latitude - step)).magnitude();
+            let parallel = f64::to_radians(latitude).cos();
+            assert!((east / parallel / north - 1.0).abs() < 1e-6);
+        }
+        // the top and bottom edges are at about ±66.2°
+        assert!(plane(Projection::Mercator, 0.0, 66.0).is_some());
+        assert!(plane(Projection::Mercator, 0.0, 67.0).is_none());
+        assert!(plane(Projection::Mercator, 0.0, -67.0).is_none());
+    }
+
+    /// plane area per unit of sphere area at a point, from a central difference Jacobian
+    fn areal_scale(projection: Projection, longitude: f64, latitude: f64) -> f64 {
+        let step = 1e-4;
+        let at = |longitude: f64, latitude: f64| plane(projection, longitude, latitude).unwrap();
+        let east = (at(longitude + step, latitude) - at(longitude - step, latitude)) / 2.0;
+        let north = (at(longitude, latitude + step) - at(longitude, latitude - step)) / 2.0;
+        let cell = step.to_radians().powi(2) * f64::to_radians(latitude).cos();
+        (east.x * north.y - east.y * north.x).abs() / cell
+    }
+
+    #[test]
+    fn equal_area_projections_are_equal_area() {
+        for projection in [
+            Projection::Mollweide,
+            Projection::Hammer,
+            Projection::LambertCylindrical,
+        ] {
+            let reference = areal_scale(projection, 0.0, 0.0);
+            for longitude in [-150.0, -60.0, 0.0, 45.0, 170.0] {
+                for latitude in [-75.0, -40.0, -10.0, 0.0, 25.0, 60.0, 80.0] {
+                    let scale = areal_scale(projection, longitude, latitude);
+                    assert!(
+                        (scale / reference - 1.0).abs() < 1e-6,
+                        "{} at ({longitude}, {latitude}): {scale} against {reference}",
+                        projection.name()
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn ellipses_and_lambert_parallels() {
+        for projection in [Projection::Mollweide, Projection::Hammer] {
+            // the poles at the middle of the top and bottom, the antimeridian at the sides
+            let pole = plane(projection, 0.0, 90.0).unwrap();
+            assert!((pole - Vector2::new(0.0, FRAC_PI_2)).magnitude() < 1e-9);
+            let side = plane(projection, 180.0, 0.0).unwrap();
+            assert!((side - Vector2::new(PI, 0.0)).magnitude() < 1e-9);
+        }
+        // the standard parallels are where the east-west and north-south scales agree
+        let standard = f64::acos((2.0 / PI).sqrt()).to_degrees();
+        assert!((standard - 37.07).abs() < 0.01);
+        let projection = Projection::LambertCylindrical;
+        let step = 1e-4;
+        let at = |longitude: f64, latitude: f64| plane(projection, longitude, latitude).unwrap();
+        let east = (at(step, standard) - at(-step, standard)).magnitude();
+        let north = (at(0.0, standard + step) - at(0.0, standard - step)).magnitude();
+        assert!((east / f64::to_radians(standard).cos() / north - 1.0).abs() < 1e-6);
+    }
+
+    /// the point of the rotated frame `distance` radians from the center, in the direction
+    /// `bearing` radians from +z towards +y
+    fn from_center(distance: f64, bearing: f64) -> Vector3<f64> {
+        let (sin, cos) = distance.sin_cos();
+        Vector3::new(cos, sin * bearing.sin(), sin * bearing.cos())
+    }
+
+    #[test]
+    fn azimuthal_equidistant_keeps_distance_and_bearing() {
+        for distance in [0.1, 1.0, 2.0, 3.0] {
+            for bearing in [0.0, 1.0, 2.5, -2.0] {
+                let uv = Projection::AzimuthalEquidistant
+                    .project(from_center(distance, bearing))
+                    .unwrap();
+                let plane = frac_to_plane(uv.x, uv.y) / AZIMUTHAL_EQUIDISTANT_SCALE;
+                assert!((plane.magnitude() - distance).abs() < 1e-12);
+                assert!((f64::atan2(plane.x, plane.y) - bearing).abs() < 1e-12);
+            }
+        }
+        let center = Projection::AzimuthalEquidistant.project(Vector3::unit_x());
+        assert_eq!(center, Some(Vector2::new(0.5, 0.5)));
+    }
+
+    #[test]
+    fn globe_shows_the_near_side_and_the_equator_as_a_ring() {
+        let (towards, _, _) = globe_axes::<f64>();
+        let center = Projection::Orthographic.project(towards).unwrap();
+        assert!((center - Vector2::new(0.5, 0.5)).magnitude() < 1e-12);
+        assert_eq!(Projection::Orthographic.project(-towards), None);
+        // the visible half of the equator is half of an ellipse, flattened by the tilt
+        let flattening = GLOBE_TILT.sin();
+        for degrees in (-90..=90).step_by(15) {
+            let t = f64::to_radians(degrees as f64);
+            let xyz = Vector3::new(t.cos(), t.sin(), 0.0);
+            let uv = Projection::Orthographic.project(xyz).unwrap();
+            let plane = frac_to_plane(uv.x, uv.y) / GLOBE_RADIUS;
+            assert!((plane.x.powi(2) + (plane.y / flattening).powi(2) - 1.0).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn gnomonic_draws_great_circles_straight() {
+        for normal in [
+            Vector3::new(0.3, -0.5, 0.8),
+            Vector3::new(-0.2, 0.9, 0.1),
+            Vector3::unit_z(),
+        ] {
+            let normal: Vector3<f64> = normal.normalize();
+            let a = normal
+                .cross(Vector3::unit_z() + Vector3::unit_x())
+                .normalize();
+            let b = normal.cross(a);
+            let points: Vec<Vector2<f64>> = (0..360)
+                .map(|degrees| f64::to_radians(degrees as f64))
+                .filter_map(|t| Projection::Gnomonic.project(a * t.cos() + b * t.sin()))
+                .collect();
+            assert!(points.len() > 10);
+            let (first, last) = (points[0], points[points.len() - 1]);
+            let direction = (last - first).normalize();
+            for point in &points {
+                let offset = *point - first;
+                assert!((offset.x * direction.y - offset.y * direction.x).abs() < 1e-12);
+            }
+        }
+        // sideways, where the widget reaches further than the limit
+        let cut_off = |degrees: f64| from_center(degrees.to_radians(), FRAC_PI_2);
+        assert!(Projection::Gnomonic.project(cut_off(69.0)).is_some());
+        assert_eq!(Projection::Gnomonic.project(cut_off(71.0)), None);
+    }
+
+    #[test]
+    fn stereographic_field_of_view_and_conformality() {
+        for degrees in [30.0, 120.0, 270.0, 350.0] {
+            let field_of_view = f32::to_radians(degrees);
+            let projection = Projection::Stereographic { field_of_view };
+            // the plane, which runs past the edges of the widget
+            let project = |distance: f64, bearing: f64| {
+                stereographic(from_center(distance, bearing), field_of_view as f64).unwrap()
+            };
+            // half the field of view reaches the top and bottom edges
+            let half = field_of_view as f64 / 2.0;
+            assert!((project(half, 0.0).y - FRAC_PI_2).abs() < 1e-6);
+            assert!((project(half, PI).y + FRAC_PI_2).abs() < 1e-6);
+            // the same stretch across as along the radius
+            let (distance, bearing, step) = (1.0, 0.7, 1e-5);
+            let radial =
+                (project(distance + step, bearing) - project(distance - step, bearing)).magnitude();
+            let across = (project(distance, bearing + step) - project(distance, bearing - step))
+                .magnitude()
+                / distance.sin();
+            assert!((radial / across - 1.0).abs() < 1e-6);
+            assert_eq!(projection.project(-Vector3::<f64>::unit_x()), None);
+        }
+    }
+}
@@ -1,36 +1,133 @@
+use std::io::Read;
+
 pub struct RawImage {
     pub width: u32,
     pub height: u32,
-    pub rgb_pixels: Vec<u8>,
+    pub rgba_pixels: Vec<u8>,
 }
 
 impl RawImage {
-    pub(crate) fn rgba_at(&self, x: usize, y: usize) -> [u8; 4] {
-        let width = self.width as usize;
-        let height = self.height as usize;
-        if x < width && y < height {
-            let base = 3 * (x + width * y);
-            [
-                self.rgb_pixels[base],
-                self.rgb_pixels[base + 1],
-                self.rgb_pixels[base + 2],
-                0xff,
-            ]
-        } else {
-            [0xff, 0, 0xff, 0xff]
+    pub(crate) fn new(width: u32, height: u32, pixels: Vec<u8>) -> Self {
+        if pixels.len() != (4 * width * height) as usize {
+            panic!("not raw RGBA?")
+        }
+        Self {
+            width,
+            height,
+            rgba_pixels: pixels,
         }
     }
+
+    /// decode a PNG of any color type and bit depth.  Images without alpha are opaque.
+    pub(crate) fn from_png(source: impl Read) -> Result<Self, String> {
+        let mut decoder = png::Decoder::new(source);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().map_err(|error| error.to_string())?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader
+            .next_frame(&mut buf)
+            .map_err(|error| error.to_string())?;
+        buf.truncate(info.buffer_size());
+
+        let pixels = match info.color_type {
+            png::ColorType::Rgba => buf,
+            png::ColorType::Rgb => buf
+                .chunks_exact(3)
+                .flat_map(|p| [p[0], p[1], p[2], 0xff])
+                .collect(),
+            png::ColorType::GrayscaleAlpha => buf
+                .chunks_exact(2)
+                .flat_map(|p| [p[0], p[0], p[0], p[1]])
+                .collect(),
+            png::ColorType::Grayscale => buf.iter().flat_map(|g| [*g, *g, *g, 0xff]).collect(),
+            // expanded by normalize_to_color8()
+            png::ColorType::Indexed => return Err("indexed PNG was not expanded".to_owned()),
+        };
+        Ok(Self::new(info.width, info.height, pixels))
+    }
 }
 
 impl RawImage {
-    pub(crate) fn new(width: u32, height: u32, pixels: Vec<u8>) -> Self {
-        if pixels.len() != (3 * width * height) as usize {
-            panic!("not raw RGB?")
+    /// the image with each color multiplied by its alpha, so that averaging or interpolating
+    /// pixels weights them by how opaque they are, and transparent ones bleed no color
+    pub(crate) fn premultiplied(mut self) -> Self {
+        for pixel in self.rgba_pixels.chunks_exact_mut(4) {
+            let alpha = pixel[3] as u32;
+            for channel in &mut pixel[..3] {
+                *channel = ((*channel as u32 * alpha + 127) / 255) as u8;
+            }
         }
-        Self {
-            width,
-            height,
-            rgb_pixels: pixels,
+        self
+    }
+
+    /// the image at half the size in each direction, each pixel the average of four.
+    /// Columns wrap around, since the image is an ERP.  Premultiply the alpha first.
+    pub(crate) fn half_size(&self) -> Self {
+        let width = self.width as usize;
+        let height = self.height as usize;
+        let half_width = (width / 2).max(1);
+        let half_height = (height / 2).max(1);
+        let mut pixels = Vec::with_capacity(4 * half_width * half_height);
+        for y in 0..half_height {
+            let rows = [2 * y, (2 * y + 1).min(height - 1)];
+            for x in 0..half_width {
+                let columns = [2 * x % width, (2 * x + 1) % width];
+                for channel in 0..4 {
+                    let sum: u32 = rows
+                        .iter()
+                        .flat_map(|row| columns.iter().map(move |column| (row, column)))
+                        .map(|(row, column)| {
+                            self.rgba_pixels[4 * (column + width * row) + channel] as u32
+                        })
+                        .sum();
+                    pixels.push(((sum + 2) / 4) as u8);
+                }
+            }
+        }
+        Self::new(half_width as u32, half_height as u32, pixels)
+    }
+
+    /// the color at a texture coordinate, interpolated between the four nearest pixels.
+    /// `u` wraps around and `v` is clamped, so there is no seam at the antimeridian or the poles.
+    pub(crate) fn bilinear(&self, u: f32, v: f32) -> [f32; 4] {
+        let width = self.width as usize;
+        let height = self.height as usize;
+        // pixel centers are at half-integer coordinates
+        let x = u * width as f32 - 0.5;
+        let y = (v * height as f32 - 0.5).clamp(0.0, (height - 1) as f32);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let column0 = (x0 as i64).rem_euclid(width as i64) as usize;
+        let column1 = (column0 + 1) % width;
+        let row0 = y0 as usize;
+        let row1 = (row0 + 1).min(height - 1);
+        let texel = |column: usize, row: usize, channel: usize| {
+            self.rgba_pixels[4 * (column + width * row) + channel] as f32
+        };
+        [0, 1, 2, 3].map(|channel| {
+            let top =
+                texel(column0, row0, channel) * (1.0 - fx) + texel(column1, row0, channel) * fx;
+            let bottom =
+                texel(column0, row1, channel) * (1.0 - fx) + texel(column1, row1, channel) * fx;
+            top * (1.0 - fy) + bottom * fy
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transparent_pixels_bleed_no_color() {
+        // opaque red beside transparent black, twice over
+        let pixels = [[0xff, 0, 0, 0xff], [0, 0, 0, 0]].repeat(4).concat();
+        let image = RawImage::new(4, 2, pixels).premultiplied();
+        let half = image.half_size();
+        for pixel in half.rgba_pixels.chunks_exact(4) {
+            assert_eq!(pixel, [0x80, 0, 0, 0x80]);
         }
+        let [r, g, b, alpha] = image.bilinear(0.25, 0.5);
+        assert_eq!([r / alpha, g, b], [1.0, 0.0, 0.0]);
     }
 }
@@ -1,115 +1,483 @@
-use cgmath::{InnerSpace, Matrix3, SquareMatrix, Vector3};
-use egui::Vec2;
-use std::f32::consts::{PI, TAU};
+//! Sphere math for remapping an ERP so the equator lies on a chosen great circle.
+//!
+//! The math is generic over [`Scalar`] so it can run in `f32` for the GUI or `f64` for accuracy.
+//! Points of the source ERP are [`LonLat`]s, and points on the sphere are [`UnitVector`]s in the
+//! world frame described in [`crate::coords`].  Points of the projected map are (u,v) fractions
+//! of the widget, packed into a plain [`Vector2`].
+//! The egui-facing API is a thin `f32` layer at the bottom of this file.
 
-pub struct GreatCircleRemapper {
-    pub matrix: Matrix3<f32>,
-    pub inverse: Matrix3<f32>,
+use crate::coords::{LonLat, UnitVector};
+use crate::projection::{clamp_unit, Projection};
+use cgmath::num_traits::{FloatConst, NumCast};
+use cgmath::{BaseFloat, InnerSpace, Matrix, Matrix3, Rad, SquareMatrix, Vector2, Vector3, Zero};
+use egui::{Ui, Vec2};
+use std::fmt;
+
+/// the floating point types the sphere math works with
+pub trait Scalar: BaseFloat + FloatConst {}
+
+impl<T: BaseFloat + FloatConst> Scalar for T {}
+
+/// convert a constant to the working precision
+pub fn scalar<S: Scalar>(value: f64) -> S {
+    <S as NumCast>::from(value).unwrap()
 }
 
-impl GreatCircleRemapper {
-    pub(crate) fn new(anchors: &[Vec2]) -> Self {
-        let matrix = Self::matrix_from_anchors(anchors);
+/// why a pair of anchors does not pick out a single great circle
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AnchorError {
+    /// the anchors are the same point, so any great circle through it would do
+    Coincident,
+    /// the anchors are on opposite sides of the sphere, so every great circle through one
+    /// passes through the other
+    Antipodal,
+    /// the anchors are spread so evenly that no great circle fits them better than another
+    NoUniqueFit,
+}
+
+impl fmt::Display for AnchorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnchorError::Coincident => write!(f, "the anchors coincide"),
+            AnchorError::Antipodal => write!(f, "the anchors are antipodal"),
+            AnchorError::NoUniqueFit => write!(f, "no great circle fits the anchors best"),
+        }
+    }
+}
+
+impl std::error::Error for AnchorError {}
+
+pub struct GreatCircleRemapper<S = f32> {
+    pub matrix: Matrix3<S>,
+    pub inverse: Matrix3<S>,
+    pub projection: Projection,
+}
 
-        let rval = Self {
+impl<S: Scalar> GreatCircleRemapper<S> {
+    pub fn from_anchors(
+        anchors: &[LonLat<S>],
+        projection: Projection,
+    ) -> Result<Self, AnchorError> {
+        let matrix = matrix_from_anchors(anchors)?;
+        Ok(Self::from_matrix(
             matrix,
-            inverse: matrix.invert().unwrap(),
+            anchors.first().copied(),
+            projection,
+        ))
+    }
+
+    /// the great circle through `anchor` heading `azimuth` radians clockwise from north.
+    /// This is the fallback for when [`Self::from_anchors`] fails.
+    pub fn from_heading(anchor: LonLat<S>, azimuth: S, projection: Projection) -> Self {
+        Self::from_matrix(
+            matrix_from_heading(anchor, azimuth),
+            Some(anchor),
+            projection,
+        )
+    }
+
+    fn from_matrix(matrix: Matrix3<S>, anchor: Option<LonLat<S>>, projection: Projection) -> Self {
+        let matrix = match anchor {
+            Some(anchor) if projection.centered_on_first_anchor() => roll_to_center(matrix, anchor),
+            _ => matrix,
         };
 
-        if true {
-            for anchor in anchors {
-                let twisted = rval.twist(anchor.x, anchor.y);
-                println!("{:?} -> {:?}", anchor, twisted);
-                println!("{:?} <- {:?}", rval.untwist(twisted.x, twisted.y), twisted);
-            }
+        Self {
+            matrix,
+            // a rotation, so the inverse is the transpose
+            inverse: matrix.transpose(),
+            projection,
         }
+    }
 
-        rval
+    /// map a point of the source ERP to the (u,v) fraction of the projected map
+    pub fn twist(&self, position: LonLat<S>) -> Option<Vector2<S>> {
+        let xyz = position.to_unit_vector();
+
+        self.projection.project((self.inverse * xyz).vector())
     }
 
-    pub fn matrix_from_anchors(anchors: &[Vec2]) -> Matrix3<f32> {
-        match anchors.len() {
-            0 => Matrix3::identity(),
-            1 => {
-                let axis_x = spherical_to_cartesian(fracv_to_radians(anchors[0]));
-                let axis_y = Vector3::cross(Vector3::new(0.0, 0.0, 1.0), axis_x).normalize();
-                let axis_z = axis_x.cross(axis_y).normalize();
-                println!("new axes {:.3?}, {:.3?}, {:.3?}", axis_x, axis_y, axis_z);
-                Matrix3::from_cols(axis_x, axis_y, axis_z)
-            }
-            _ => {
-                let anchor1 = anchors[0];
-                let anchor2 = anchors[1];
-                let anchor1 = spherical_to_cartesian(fracv_to_radians(anchor1));
-                let anchor2 = spherical_to_cartesian(fracv_to_radians(anchor2));
-                let axis_x = ((anchor1 + anchor2) * 0.5).normalize();
-                let axis_z = anchor1.cross(anchor2).normalize();
-                let axis_y = axis_z.cross(axis_x).normalize();
-                Matrix3::from_cols(axis_x, axis_y, axis_z)
+    /// map the (u,v) fraction of the projected map back to a point of the source ERP
+    pub fn untwist(&self, uv: Vector2<S>) -> Option<LonLat<S>> {
+        let xyz = self.projection.unproject(uv.x, uv.y)?;
+
+        Some(UnitVector::new(self.matrix * xyz).to_lon_lat())
+    }
+}
+
+/// the rotation whose equator is the great circle through the first two anchors.
+/// A single anchor gets the great circle heading east from it.
+pub fn matrix_from_anchors<S: Scalar>(anchors: &[LonLat<S>]) -> Result<Matrix3<S>, AnchorError> {
+    match anchors.len() {
+        0 => Ok(Matrix3::identity()),
+        1 => Ok(matrix_from_heading(anchors[0], S::FRAC_PI_2())),
+        _ => {
+            let anchor1 = anchors[0].to_unit_vector().vector();
+            let anchor2 = anchors[1].to_unit_vector().vector();
+            let axis_z = anchor1.cross(anchor2);
+            if axis_z.magnitude() < scalar(1e-6) {
+                return Err(if anchor1.dot(anchor2) > S::zero() {
+                    AnchorError::Coincident
+                } else {
+                    AnchorError::Antipodal
+                });
             }
+            let axis_x = ((anchor1 + anchor2) * scalar(0.5)).normalize();
+            let axis_z = axis_z.normalize();
+            let axis_y = axis_z.cross(axis_x).normalize();
+            Ok(Matrix3::from_cols(axis_x, axis_y, axis_z))
         }
     }
+}
 
-    pub(crate) fn twist(&self, longitude_frac: f32, latitude_frac: f32) -> Vec2 {
-        let theta_phi = frac_to_radians(longitude_frac, latitude_frac);
+/// the rotation whose equator is the great circle that best fits all of the anchors, in the
+/// least squares sense, along with the RMS distance in radians of the anchors from it.
+///
+/// The pole of the fitted circle is the eigenvector of the smallest eigenvalue of the scatter
+/// matrix `Σ p pᵀ`.  For two anchors this is the same circle as [`matrix_from_anchors`].
+pub fn best_fit_matrix<S: Scalar>(anchors: &[LonLat<S>]) -> Result<(Matrix3<S>, S), AnchorError> {
+    let points: Vec<Vector3<S>> = anchors
+        .iter()
+        .map(|anchor| anchor.to_unit_vector().vector())
+        .collect();
+    let Some(&first) = points.first() else {
+        return Ok((Matrix3::identity(), S::zero()));
+    };
 
-        let xyz = spherical_to_cartesian(theta_phi);
+    let scatter = points.iter().fold(Matrix3::zero(), |sum, p| {
+        sum + Matrix3::from_cols(*p * p.x, *p * p.y, *p * p.z)
+    });
+    let (eigenvalues, eigenvectors) = symmetric_eigen(scatter);
+    // how far rounding can move an eigenvalue
+    let noise = scalar::<S>(8.0) * S::epsilon() * eigenvalues[2];
+    // for two anchors an angle θ apart λ1/λ2 is tan²(θ/2), so this agrees with the 1e-6 of
+    // matrix_from_anchors, as far as the precision allows
+    if eigenvalues[1] < (scalar::<S>(0.25e-12) * eigenvalues[2]).max(noise * scalar(4.0)) {
+        // all of the anchors are on one line through the center, so any circle through them fits
+        return Err(if points.iter().all(|p| p.dot(first) > S::zero()) {
+            AnchorError::Coincident
+        } else {
+            AnchorError::Antipodal
+        });
+    }
+    if eigenvalues[1] - eigenvalues[0] < (scalar::<S>(1e-6) * eigenvalues[1]).max(noise) {
+        // the smallest eigenvalue is repeated, as for anchors spaced evenly around a circle or
+        // along three perpendicular axes, so the pole could be anywhere in a plane
+        return Err(AnchorError::NoUniqueFit);
+    }
 
-        cartesian_to_lat_long(self.inverse * xyz)
+    let mut axis_z = eigenvectors[0];
+    // travel from the first anchor towards the last, like matrix_from_anchors
+    let last = points[points.len() - 1];
+    if first.cross(last).dot(axis_z) < S::zero() {
+        axis_z = -axis_z;
     }
+    let center = points.iter().fold(Vector3::zero(), |sum, p| sum + *p);
+    let center = center - axis_z * center.dot(axis_z);
+    let center = if center.magnitude() > scalar(1e-6) {
+        center
+    } else {
+        first - axis_z * first.dot(axis_z)
+    };
+    let axis_x = center.normalize();
+    let axis_y = axis_z.cross(axis_x).normalize();
 
-    pub(crate) fn untwist(&self, longitude_frac: f32, latitude_frac: f32) -> Vec2 {
-        let theta_phi = frac_to_radians(longitude_frac, latitude_frac);
+    let squares = points
+        .iter()
+        .map(|p| {
+            let cross_track = clamp_unit(p.dot(axis_z)).asin();
+            cross_track * cross_track
+        })
+        .fold(S::zero(), |sum, square| sum + square);
+    let rms = (squares / scalar(points.len() as f64)).sqrt();
 
-        let xyz = spherical_to_cartesian(theta_phi);
+    Ok((Matrix3::from_cols(axis_x, axis_y, axis_z), rms))
+}
 
-        cartesian_to_lat_long(self.matrix * xyz)
+/// eigenvalues in ascending order, and their unit eigenvectors, of a symmetric matrix.
+/// Uses cyclic Jacobi rotations, which is plenty for 3×3.
+fn symmetric_eigen<S: Scalar>(matrix: Matrix3<S>) -> ([S; 3], [Vector3<S>; 3]) {
+    let mut a: [[S; 3]; 3] = matrix.into();
+    let mut v: [[S; 3]; 3] = Matrix3::identity().into();
+    for _sweep in 0..50 {
+        let off_diagonal = a[0][1] * a[0][1] + a[0][2] * a[0][2] + a[1][2] * a[1][2];
+        if off_diagonal < scalar(1e-30) {
+            break;
+        }
+        for (p, q) in [(0, 1), (0, 2), (1, 2)] {
+            if a[p][q] == S::zero() {
+                continue;
+            }
+            // the rotation in the p,q plane that zeroes a[p][q]
+            let theta = (a[q][q] - a[p][p]) / (scalar::<S>(2.0) * a[p][q]);
+            let t = theta.signum() / (theta.abs() + (theta * theta + S::one()).sqrt());
+            let c = S::one() / (t * t + S::one()).sqrt();
+            let s = t * c;
+            for row in a.iter_mut() {
+                let (akp, akq) = (row[p], row[q]);
+                row[p] = c * akp - s * akq;
+                row[q] = s * akp + c * akq;
+            }
+            for k in 0..3 {
+                let (apk, aqk) = (a[p][k], a[q][k]);
+                a[p][k] = c * apk - s * aqk;
+                a[q][k] = s * apk + c * aqk;
+            }
+            for row in v.iter_mut() {
+                let (vkp, vkq) = (row[p], row[q]);
+                row[p] = c * vkp - s * vkq;
+                row[q] = s * vkp + c * vkq;
+            }
+        }
     }
+
+    let mut order = [0, 1, 2];
+    order.sort_by(|i, j| a[*i][*i].partial_cmp(&a[*j][*j]).unwrap());
+    // `v` is indexed [row][column], with the eigenvectors in its columns
+    let eigenvector = |i: usize| Vector3::new(v[0][i], v[1][i], v[2][i]).normalize();
+    (order.map(|i| a[i][i]), order.map(eigenvector))
 }
 
-pub fn transform_ll_to_ll(longitude_frac: f32, latitude_frac: f32, matrix: &Matrix3<f32>) -> Vec2 {
-    let theta_phi = frac_to_radians(longitude_frac, latitude_frac);
+/// the rotation that puts `anchor` at `(1,0,0)` with the equator leaving it `azimuth` radians
+/// clockwise from north.  Unlike [`matrix_from_anchors`] this is defined everywhere; at the poles
+/// "north" is taken along the anchor's meridian.
+pub fn matrix_from_heading<S: Scalar>(anchor: LonLat<S>, azimuth: S) -> Matrix3<S> {
+    let axis_x = anchor.to_unit_vector().vector();
+    let (sin_lon, cos_lon) = anchor.longitude.sin_cos();
+    let (sin_lat, cos_lat) = anchor.latitude.sin_cos();
+    // the derivatives of LonLat::to_unit_vector, less the cos(latitude) factor of the east one
+    let east = Vector3::new(-sin_lon, cos_lon, S::zero());
+    let north = Vector3::new(-sin_lat * cos_lon, -sin_lat * sin_lon, -cos_lat);
+    let axis_y = (north * azimuth.cos() + east * azimuth.sin()).normalize();
+    let axis_z = axis_x.cross(axis_y).normalize();
+    Matrix3::from_cols(axis_x, axis_y, axis_z)
+}
 
-    let xyz = spherical_to_cartesian(theta_phi);
+/// roll the rotated frame along its equator so that `anchor` ends up at `(1,0,0)`.
+/// For the matrix from [`matrix_from_anchors`] the second anchor then lies on the
+/// equator to the east of the center.
+pub fn roll_to_center<S: Scalar>(matrix: Matrix3<S>, anchor: LonLat<S>) -> Matrix3<S> {
+    let xyz = (matrix.transpose() * anchor.to_unit_vector()).vector();
+    matrix * Matrix3::from_angle_z(Rad(S::atan2(xyz.y, xyz.x)))
+}
 
-    cartesian_to_lat_long(matrix * xyz)
+pub fn transform_lon_lat<S: Scalar>(position: LonLat<S>, matrix: &Matrix3<S>) -> LonLat<S> {
+    (*matrix * position.to_unit_vector()).to_lon_lat()
 }
 
-fn cartesian_to_lat_long(xyz: Vector3<f32>) -> Vec2 {
-    let r = Vec2::new(xyz.x, xyz.y).length();
-    let phi = f32::atan2(xyz.z, r);
-    let theta = f32::atan2(-xyz.y, -xyz.x);
-    let v = phi / PI + 0.5;
-    let u = theta / TAU;
+/// `count` points evenly spaced along the shorter great circle arc from `a` to `b`, inclusive
+pub fn great_circle_arc<S: Scalar>(
+    a: UnitVector<S>,
+    b: UnitVector<S>,
+    count: usize,
+) -> Vec<UnitVector<S>> {
+    let angle = a.angle(b);
+    let sin = angle.sin();
+    if sin.abs() < scalar(1e-6) {
+        // coincident (or antipodal, where the arc is not defined)
+        return vec![a; count];
+    }
+    let (a, b) = (a.vector(), b.vector());
+    (0..count)
+        .map(|i| {
+            let t: S = scalar(i as f64 / (count - 1).max(1) as f64);
+            UnitVector::new((a * ((S::one() - t) * angle).sin() + b * (t * angle).sin()) / sin)
+        })
+        .collect()
+}
+
+//
 
-    Vec2::new(my_fmod(u, 1.0), my_fmod(v, 1.0))
+pub(crate) fn to_vec2(v: Vector2<f32>) -> Vec2 {
+    Vec2::new(v.x, v.y)
 }
 
-fn my_fmod(a: f32, b: f32) -> f32 {
-    let rval = a % b;
-    if a < 0.0 {
-        rval + b
-    } else {
-        rval
-    }
+pub(crate) fn from_vec2(v: Vec2) -> Vector2<f32> {
+    Vector2::new(v.x, v.y)
 }
 
-fn frac_to_radians(longitude_frac: f32, latitude_frac: f32) -> Vec2 {
-    let theta = longitude_frac * TAU;
-    let phi = (latitude_frac - 0.5) * PI;
-    Vec2::new(theta, phi)
+impl GreatCircleRemapper<f32> {
+    pub(crate) fn new(anchors: &[LonLat], projection: Projection) -> Result<Self, AnchorError> {
+        let rval = Self::from_anchors(anchors, projection)?;
+
+        if true {
+            for anchor in anchors {
+                let twisted = rval.twist(*anchor);
+                println!("{:?} -> {:?}", anchor, twisted);
+                if let Some(twisted) = twisted {
+                    println!("{:?} <- {:?}", rval.untwist(twisted), twisted);
+                }
+            }
+        }
+
+        Ok(rval)
+    }
+
+    /// [`Self::twist`] into a widget fraction
+    pub(crate) fn twist_vec2(&self, position: LonLat) -> Option<Vec2> {
+        self.twist(position).map(to_vec2)
+    }
+
+    /// [`Self::untwist`] from a widget fraction
+    pub(crate) fn untwist_vec2(&self, uv: Vec2) -> Option<LonLat> {
+        self.untwist(from_vec2(uv))
+    }
 }
 
-fn fracv_to_radians(uv: Vec2) -> Vec2 {
-    frac_to_radians(uv.x, uv.y)
+/// explain why the anchors were rejected and let the user choose the heading of the great circle
+/// through the first anchor instead, unless no heading would help.  Returns true if `azimuth`
+/// (radians) changed.
+pub(crate) fn heading_fallback_ui(ui: &mut Ui, error: AnchorError, azimuth: &mut f32) -> bool {
+    if error == AnchorError::NoUniqueFit {
+        ui.label(format!("{error}, so the view is unchanged"));
+        return false;
+    }
+    ui.label(format!("{error}, so pick a heading:"));
+    let mut degrees = azimuth.to_degrees();
+    let changed = ui
+        .add(
+            egui::DragValue::new(&mut degrees)
+                .speed(0.5)
+                .clamp_range(0.0..=360.0)
+                .suffix("°"),
+        )
+        .changed();
+    if changed {
+        *azimuth = degrees.to_radians();
+    }
+    changed
 }
 
-fn spherical_to_cartesian(Vec2 { x: theta, y: phi }: Vec2) -> Vector3<f32> {
-    let z = phi.sin();
-    let r = phi.cos();
-    let x = -theta.cos() * r;
-    let y = -theta.sin() * r;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// one second of arc, in radians
+    const ARC_SECOND: f64 = 1.0 / 3600.0 * std::f64::consts::PI / 180.0;
 
-    let xyz: Vector3<f32> = Vector3::new(x, y, z);
-    xyz
+    /// anchors spread over the whole sphere, poles and antimeridian included
+    fn anchors() -> Vec<LonLat<f64>> {
+        let mut anchors = vec![];
+        for latitude in [-90.0, -67.5, -30.0, -1e-3, 0.0, 12.5, 45.0, 89.9, 90.0] {
+            for longitude in [-180.0, -135.0, -45.5, 0.0, 10.0, 90.0, 179.999, 180.0] {
+                anchors.push(LonLat::from_degrees(longitude, latitude));
+            }
+        }
+        anchors
+    }
+
+    fn angle(a: LonLat<f64>, b: LonLat<f64>) -> f64 {
+        a.to_unit_vector().angle(b.to_unit_vector())
+    }
+
+    #[test]
+    fn twist_untwist_round_trip_within_an_arc_second() {
+        let anchors = anchors();
+        for projection in Projection::ALL {
+            for pair in anchors.windows(2).step_by(3) {
+                let Ok(remapper) = GreatCircleRemapper::<f64>::from_anchors(pair, projection)
+                else {
+                    continue;
+                };
+                for position in &anchors {
+                    let Some(uv) = remapper.twist(*position) else {
+                        continue;
+                    };
+                    let back = remapper.untwist(uv).unwrap_or_else(|| {
+                        panic!(
+                            "{} from {pair:?}: {position:?} at {uv:?}",
+                            projection.name()
+                        )
+                    });
+                    let error = angle(*position, back);
+                    assert!(
+                        error < ARC_SECOND,
+                        "{} from {pair:?}: {position:?} came back {:.3}″ away",
+                        projection.name(),
+                        error / ARC_SECOND
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn anchors_land_on_the_equator() {
+        let anchors = anchors();
+        for pair in anchors.windows(2) {
+            let Ok(matrix) = matrix_from_anchors(pair) else {
+                continue;
+            };
+            for anchor in pair {
+                let rotated = transform_lon_lat(*anchor, &matrix.transpose());
+                assert!(rotated.latitude.abs() < 1e-12, "{pair:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn degenerate_anchors() {
+        let london = LonLat::from_degrees(-0.1, 51.5);
+        let antipode = LonLat::from_degrees(179.9, -51.5);
+        assert_eq!(
+            matrix_from_anchors(&[london, london]),
+            Err(AnchorError::Coincident)
+        );
+        assert_eq!(
+            matrix_from_anchors(&[london, antipode]),
+            Err(AnchorError::Antipodal)
+        );
+    }
+
+    #[test]
+    fn best_fit_of_two_anchors_is_their_great_circle() {
+        let anchors = anchors();
+        for pair in anchors.windows(2) {
+            let Ok(matrix) = matrix_from_anchors(pair) else {
+                continue;
+            };
+            let (fitted, rms) = best_fit_matrix(pair).unwrap();
+            assert!(rms < 1e-9);
+            // the same pole; the fit may be centered elsewhere on the circle
+            assert!((fitted.z - matrix.z).magnitude() < 1e-9, "{pair:?}");
+        }
+    }
+
+    #[test]
+    fn best_fit_rms_is_the_cross_track_distance() {
+        // on the equator and a degree either side of it
+        let anchors: [LonLat<f64>; 4] = [
+            LonLat::from_degrees(0.0, 1.0),
+            LonLat::from_degrees(90.0, -1.0),
+            LonLat::from_degrees(180.0, 1.0),
+            LonLat::from_degrees(-90.0, -1.0),
+        ];
+        let (matrix, rms) = best_fit_matrix(&anchors).unwrap();
+        assert!((matrix.z.z.abs() - 1.0).abs() < 1e-9);
+        assert!((rms - 1.0_f64.to_radians()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn best_fit_of_symmetric_anchors_is_not_unique() {
+        let axes: [LonLat<f64>; 3] = [
+            LonLat::from_degrees(0.0, 0.0),
+            LonLat::from_degrees(90.0, 0.0),
+            LonLat::from_degrees(0.0, 90.0),
+        ];
+        assert_eq!(best_fit_matrix(&axes), Err(AnchorError::NoUniqueFit));
+        let ring: Vec<LonLat<f64>> = (0..6)
+            .map(|i| LonLat::from_degrees(i as f64 * 60.0, 80.0))
+            .collect();
+        assert_eq!(best_fit_matrix(&ring), Err(AnchorError::NoUniqueFit));
+        let london = LonLat::from_degrees(-0.1, 51.5);
+        let antipode = LonLat::from_degrees(179.9, -51.5);
+        assert_eq!(
+            best_fit_matrix(&[london, london, london]),
+            Err(AnchorError::Coincident)
+        );
+        assert_eq!(
+            best_fit_matrix(&[london, antipode, london]),
+            Err(AnchorError::Antipodal)
+        );
+    }
 }
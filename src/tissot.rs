@@ -0,0 +1,180 @@
+//! Tissot's indicatrix: how the map distorts a small circle around each point of the sphere.
+//!
+//! The Jacobian is taken numerically through [`Projection::project`], so it covers every
+//! projection and includes the view rotation.  Its columns are the movement of the (u,v)
+//! fraction of the widget per radian east and per radian north, so the image of a small circle
+//! of radius `r` is the ellipse `J·r(cos α, sin α)`.
+
+use crate::coords::LonLat;
+use crate::projection::Projection;
+use crate::remapper::{scalar, to_vec2, Scalar};
+use cgmath::{InnerSpace, Matrix, Matrix2, Matrix3, Vector2, Vector3};
+use egui::{Ui, Vec2};
+
+/// the local linear approximation of the map at one point
+pub struct Indicatrix<S = f32> {
+    /// where the point is drawn, as a (u,v) fraction of the widget
+    pub center: Vector2<S>,
+    /// columns are d(u,v)/d(east) and d(u,v)/d(north), per radian
+    pub jacobian: Matrix2<S>,
+}
+
+impl<S: Scalar> Indicatrix<S> {
+    /// the indicatrix at `position` for a view whose rotated frame maps to the world by `matrix`.
+    /// Returns `None` if the point, or both sides of it, are off the map.
+    pub fn at(projection: &Projection, matrix: &Matrix3<S>, position: LonLat<S>) -> Option<Self> {
+        let inverse = matrix.transpose();
+        let project = |xyz: Vector3<S>| projection.project(inverse * xyz);
+        let center_xyz = position.to_unit_vector().vector();
+        let center = project(center_xyz)?;
+
+        let (sin_lon, cos_lon) = position.longitude.sin_cos();
+        let (sin_lat, cos_lat) = position.latitude.sin_cos();
+        // the derivatives of LonLat::to_unit_vector, as unit vectors
+        let east = Vector3::new(-sin_lon, cos_lon, S::zero());
+        let north = Vector3::new(-sin_lat * cos_lon, -sin_lat * sin_lon, -cos_lat);
+
+        let step: S = scalar(1e-3);
+        let derivative = |direction: Vector3<S>| {
+            let moved = |h: S| project(center_xyz * h.cos() + direction * h.sin());
+            let forward = moved(step).map(|uv| (uv - center) / step);
+            let backward = moved(-step).map(|uv| (center - uv) / step);
+            match (forward, backward) {
+                // the two sides disagree when the step crosses a seam; keep the side that didn't
+                (Some(f), Some(b)) if (f - b).magnitude() > f.magnitude().min(b.magnitude()) => {
+                    Some(if f.magnitude() < b.magnitude() { f } else { b })
+                }
+                (Some(f), Some(b)) => Some((f + b) * scalar(0.5)),
+                (f, b) => f.or(b),
+            }
+        };
+
+        Some(Self {
+            center,
+            jacobian: Matrix2::from_cols(derivative(east)?, derivative(north)?),
+        })
+    }
+
+    /// the Jacobian in the units of the projection's plane, where the widget spans 2π by π.
+    /// Unlike (u,v) fractions these are the same in both directions, so its singular values are
+    /// the linear scale factors of the map.
+    pub fn plane_jacobian(&self) -> Matrix2<S> {
+        let to_plane = Matrix2::from_cols(
+            Vector2::new(S::TAU(), S::zero()),
+            Vector2::new(S::zero(), S::PI()),
+        );
+        to_plane * self.jacobian
+    }
+
+    /// `count` points around the image of a circle of `radius` radians, as (u,v) fractions
+    pub fn outline(&self, radius: S, count: usize) -> Vec<Vector2<S>> {
+        (0..count)
+            .map(|i| {
+                let angle = S::TAU() * scalar(i as f64 / count as f64);
+                self.center + self.jacobian * Vector2::new(angle.cos(), angle.sin()) * radius
+            })
+            .collect()
+    }
+}
+
+/// a regular grid of points every `spacing` degrees, including the equator and the prime
+/// meridian but not the poles
+pub fn grid<S: Scalar>(spacing: S) -> Vec<LonLat<S>> {
+    let ninety: S = scalar(90.0);
+    let columns = (scalar::<S>(360.0) / spacing)
+        .floor()
+        .to_usize()
+        .unwrap_or(0);
+    let rows = ((ninety - scalar(1e-6)) / spacing)
+        .floor()
+        .to_usize()
+        .unwrap_or(0);
+    (0..columns)
+        .flat_map(|column| {
+            let longitude = scalar::<S>(-180.0) + spacing * scalar(column as f64);
+            (0..=2 * rows).map(move |row| {
+                let latitude = spacing * (scalar::<S>(row as f64) - scalar(rows as f64));
+                LonLat::from_degrees(longitude, latitude)
+            })
+        })
+        .collect()
+}
+
+/// the indicatrix settings of a map widget
+pub(crate) struct TissotOptions {
+    pub show: bool,
+    /// degrees between the centers of the circles
+    pub spacing: f32,
+}
+
+impl Default for TissotOptions {
+    fn default() -> Self {
+        Self {
+            show: false,
+            spacing: 30.0,
+        }
+    }
+}
+
+impl TissotOptions {
+    /// the outlines to draw, as (u,v) fractions of the widget.  Ellipses that blow up near a
+    /// singularity of the projection are left out.
+    pub fn outlines(&self, projection: &Projection, matrix: &Matrix3<f32>) -> Vec<Vec<Vec2>> {
+        if !self.show {
+            return vec![];
+        }
+        // a fifth of the spacing leaves clear gaps between neighbours on an undistorted map
+        let radius = (self.spacing * 0.2).to_radians();
+        grid(self.spacing)
+            .into_iter()
+            .filter_map(|position| Indicatrix::at(projection, matrix, position))
+            .map(|indicatrix| (indicatrix.center, indicatrix.outline(radius, 48)))
+            .filter(|(center, outline)| outline.iter().all(|p| (p - center).magnitude() < 0.25))
+            .map(|(_, outline)| outline.into_iter().map(to_vec2).collect())
+            .collect()
+    }
+
+    pub fn ui(&mut self, ui: &mut Ui) {
+        ui.checkbox(&mut self.show, "Tissot")
+            .on_hover_text("draw the image of small circles of equal size on the sphere");
+        if self.show {
+            ui.add(
+                egui::DragValue::new(&mut self.spacing)
+                    .speed(0.5)
+                    .clamp_range(5.0..=90.0)
+                    .suffix("° apart"),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::SquareMatrix;
+
+    /// (|d/d east|, |d/d north|) of the plane Jacobian, i.e. the scale along the parallel and
+    /// along the meridian
+    fn parallel_and_meridian_scale(projection: Projection, lon: f64, lat: f64) -> (f64, f64) {
+        let position = LonLat::from_degrees(lon, lat);
+        let jacobian = Indicatrix::at(&projection, &Matrix3::identity(), position)
+            .unwrap_or_else(|| panic!("{}: ({lon}, {lat}) is off the map", projection.name()))
+            .plane_jacobian();
+        (jacobian.x.magnitude(), jacobian.y.magnitude())
+    }
+
+    #[test]
+    fn jacobian_matches_closed_forms() {
+        for lon in [-150.0, -60.0, 0.0, 45.0, 120.0] {
+            for lat in [-60.0, -30.0, 0.0, 15.0, 50.0] {
+                let secant = 1.0 / f64::to_radians(lat).cos();
+                let (k, h) = parallel_and_meridian_scale(Projection::Equirectangular, lon, lat);
+                assert!((k / secant - 1.0).abs() < 1e-5, "k = {k} at ({lon}, {lat})");
+                assert!((h - 1.0).abs() < 1e-5, "h = {h} at ({lon}, {lat})");
+                let (k, h) = parallel_and_meridian_scale(Projection::Mercator, lon, lat);
+                assert!((k / secant - 1.0).abs() < 1e-5, "k = {k} at ({lon}, {lat})");
+                assert!((h / secant - 1.0).abs() < 1e-5, "h = {h} at ({lon}, {lat})");
+            }
+        }
+    }
+}
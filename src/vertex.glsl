@@ -1,8 +1,11 @@
 #version 300 es
 
 in vec2 vert;
+// (left, top, width, height) of the part of the map shown, in map fractions.  See Viewport::uniform()
+uniform vec4 viewport;
 out vec2 tex_coord;
 void main() {
     gl_Position = vec4(vert, 0.0, 1.0);
-    tex_coord = (vert * vec2(1.0, -1.0) + 1.0)*0.5;
+    vec2 widget = (vert * vec2(1.0, -1.0) + 1.0)*0.5;
+    tex_coord = viewport.xy + widget * viewport.zw;
 }
@@ -0,0 +1,145 @@
+//! Pan and zoom over the projected map.
+//!
+//! The map is laid out on the unit square of (u,v) fractions (see [`crate::projection`]).  The
+//! widget shows a square window onto it, `1/zoom` on a side, around `center`.  vertex.glsl
+//! applies the same transform, so the shader and the hover and click math agree.
+
+use egui::{Pos2, Rect, Vec2};
+
+/// zooming further than this only magnifies single texels
+const MAX_ZOOM: f32 = 64.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Viewport {
+    /// the (u,v) fraction of the map at the middle of the widget
+    pub center: Vec2,
+    /// 1 shows the whole map
+    pub zoom: f32,
+}
+
+impl Default for Viewport {
+    fn default() -> Self {
+        Self {
+            center: Vec2::splat(0.5),
+            zoom: 1.0,
+        }
+    }
+}
+
+impl Viewport {
+    pub fn is_zoomed(&self) -> bool {
+        self.zoom > 1.0
+    }
+
+    /// the (u,v) fraction of the map under a fraction of the widget
+    pub fn to_map(&self, widget: Vec2) -> Vec2 {
+        self.center + (widget - Vec2::splat(0.5)) / self.zoom
+    }
+
+    /// the fraction of the widget where a (u,v) fraction of the map is drawn
+    pub fn to_widget(&self, map: Vec2) -> Vec2 {
+        (map - self.center) * self.zoom + Vec2::splat(0.5)
+    }
+
+    /// zoom by `factor`, keeping the point of the map under `widget` where it is
+    pub fn zoom_about(&mut self, widget: Vec2, factor: f32) {
+        let fixed = self.to_map(widget);
+        self.zoom = (self.zoom * factor).clamp(1.0, MAX_ZOOM);
+        self.center = fixed - (widget - Vec2::splat(0.5)) / self.zoom;
+        self.clamp();
+    }
+
+    /// move the map along with the pointer, by `widget_delta` fractions of the widget
+    pub fn pan(&mut self, widget_delta: Vec2) {
+        self.center -= widget_delta / self.zoom;
+        self.clamp();
+    }
+
+    /// keep the window inside the map
+    fn clamp(&mut self) {
+        let half = 0.5 / self.zoom;
+        self.center.x = self.center.x.clamp(half, 1.0 - half);
+        self.center.y = self.center.y.clamp(half, 1.0 - half);
+    }
+
+    /// for the `viewport` uniform in vertex.glsl: the map fraction at the top left of the widget
+    /// and the span of the widget in map fractions
+    pub fn uniform(&self) -> [f32; 4] {
+        self.uniform_for(Rect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0)))
+    }
+
+    /// [`Self::uniform`] for drawing only `part` of the widget, given in fractions of the widget.
+    /// Used to render a large image in tiles.
+    pub fn uniform_for(&self, part: Rect) -> [f32; 4] {
+        let top_left = self.to_map(part.min.to_vec2());
+        let span = part.size() / self.zoom;
+        [top_left.x, top_left.y, span.x, span.y]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points() -> impl Iterator<Item = Vec2> {
+        (0..=4).flat_map(|i| (0..=4).map(move |j| Vec2::new(i as f32, j as f32) / 4.0))
+    }
+
+    #[test]
+    fn to_map_undoes_to_widget() {
+        let viewports = [
+            Viewport::default(),
+            Viewport {
+                center: Vec2::new(0.3, 0.6),
+                zoom: 2.5,
+            },
+            Viewport {
+                center: Vec2::new(0.9, 0.1),
+                zoom: MAX_ZOOM,
+            },
+        ];
+        for viewport in viewports {
+            for p in points() {
+                let back = viewport.to_map(viewport.to_widget(p));
+                assert!(
+                    (back - p).length() < 1e-6,
+                    "{viewport:?}: {p:?} came back as {back:?}"
+                );
+                let back = viewport.to_widget(viewport.to_map(p));
+                assert!(
+                    (back - p).length() < 1e-5,
+                    "{viewport:?}: {p:?} came back as {back:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn zoom_about_keeps_the_point_under_the_pointer() {
+        for widget in points() {
+            let mut viewport = Viewport::default();
+            let fixed = viewport.to_map(widget);
+            // zooming in never pushes the window off the map, so nothing is clamped
+            for factor in [1.5, 2.0, 3.0] {
+                viewport.zoom_about(widget, factor);
+                let now = viewport.to_map(widget);
+                assert!((now - fixed).length() < 1e-6, "{widget:?} moved to {now:?}");
+            }
+            // and zooming back out retraces the same windows
+            for factor in [1.0 / 3.0, 0.5] {
+                viewport.zoom_about(widget, factor);
+                let now = viewport.to_map(widget);
+                assert!((now - fixed).length() < 1e-6, "{widget:?} moved to {now:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn zoom_is_clamped_and_the_window_stays_on_the_map() {
+        let mut viewport = Viewport::default();
+        viewport.zoom_about(Vec2::new(0.0, 1.0), 1000.0);
+        assert_eq!(viewport.zoom, MAX_ZOOM);
+        viewport.zoom_about(Vec2::splat(0.5), 1e-3);
+        assert_eq!(viewport, Viewport::default());
+    }
+}
@@ -1,6 +1,12 @@
+use crate::distortion::{reference_scale, DistortionHeatmap};
+use crate::layers::{BlendMode, ImageLayer, MAX_LAYERS};
+use crate::projection::Projection;
+use crate::raw_image::RawImage;
+use crate::viewport::Viewport;
 use crate::world_map::WorldSampler;
 use eframe::glow;
 use eframe::glow::HasContext;
+use egui::{Pos2, Rect, Vec2};
 use std::sync::Arc;
 /*use wasm_bindgen::prelude::wasm_bindgen;
 
@@ -17,13 +23,12 @@ extern "C" {
 pub struct WorldGLSL<C: HasContext> {
     pub program: C::Program,
     pub vertex_array: VertexBufferHolder<C, f32>,
-    texture: C::Texture,
     // we can't persist these because they are not Send
     // sul_world: C::UniformLocation,
     // sul_matrix: C::UniformLocation,
 }
 
-/// object that can use GLSL to paint the world map as an ERP that has been rotated by a matrix.
+/// object that can use GLSL to paint a stack of ERP layers that have been rotated by a matrix.
 impl<C: HasContext> WorldGLSL<C> {
     pub(crate) fn new(gl: &Arc<C>) -> Self {
         /* let shader_version = ShaderVersion::get(gl);
@@ -50,42 +55,48 @@ impl<C: HasContext> WorldGLSL<C> {
                 2,
             );
 
-            let tex = { Self::world_map_texture(gl).unwrap() };
-
             Self {
                 program,
                 vertex_array,
-                texture: tex,
                 // sul_world,
                 // sul_matrix,
             }
         }
     }
 
-    unsafe fn world_map_texture(gl: &Arc<C>) -> Result<C::Texture, String> {
-        let image = WorldSampler::raw_world_map();
+    /// `image` has premultiplied alpha, so the mipmaps are weighted by it
+    unsafe fn layer_texture(gl: &Arc<C>, image: &RawImage) -> Result<C::Texture, String> {
         let tex: C::Texture = gl.create_texture()?;
         gl.bind_texture(glow::TEXTURE_2D, Some(tex));
         gl.tex_image_2d(
             glow::TEXTURE_2D,
             0,
-            glow::RGB as i32,
+            glow::RGBA as i32,
             image.width as i32,
             image.height as i32,
             0,
-            glow::RGB,
+            glow::RGBA,
             glow::UNSIGNED_BYTE,
-            Some(image.rgb_pixels.as_slice()),
+            Some(image.rgba_pixels.as_slice()),
         );
+        gl.generate_mipmap(glow::TEXTURE_2D);
         gl.tex_parameter_i32(
             glow::TEXTURE_2D,
             glow::TEXTURE_MAG_FILTER,
-            glow::NEAREST as i32,
+            glow::LINEAR as i32,
         );
         gl.tex_parameter_i32(
             glow::TEXTURE_2D,
             glow::TEXTURE_MIN_FILTER,
-            glow::NEAREST as i32,
+            glow::LINEAR_MIPMAP_LINEAR as i32,
+        );
+        // the ERP is continuous across the antimeridian, but not across the poles.
+        // fragment.glsl supplies gradients that do not jump where u wraps.
+        gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_WRAP_S, glow::REPEAT as i32);
+        gl.tex_parameter_i32(
+            glow::TEXTURE_2D,
+            glow::TEXTURE_WRAP_T,
+            glow::CLAMP_TO_EDGE as i32,
         );
         Ok(tex)
     }
@@ -144,27 +155,324 @@ impl<C: HasContext> WorldGLSL<C> {
         shader
     }
 
-    pub(crate) fn paint(&self, gl: &Arc<C>, rotation: &[f32]) {
+    pub(crate) fn paint(
+        &self,
+        gl: &Arc<C>,
+        rotation: &[f32],
+        projection: Projection,
+        heatmap: DistortionHeatmap,
+        layers: &[ShaderLayer<C::Texture>],
+        viewport: &Viewport,
+    ) {
+        unsafe {
+            self.draw(
+                gl,
+                rotation,
+                projection,
+                heatmap,
+                layers,
+                viewport.uniform(),
+            )
+        }
+    }
+
+    /// draw over the whole of the current GL viewport the part of the map picked out by
+    /// `viewport`, a value for the `viewport` uniform in vertex.glsl
+    unsafe fn draw(
+        &self,
+        gl: &Arc<C>,
+        rotation: &[f32],
+        projection: Projection,
+        heatmap: DistortionHeatmap,
+        layers: &[ShaderLayer<C::Texture>],
+        viewport: [f32; 4],
+    ) {
         unsafe {
-            let sul_world: C::UniformLocation =
-                gl.get_uniform_location(self.program, "world").unwrap();
             let sul_matrix: C::UniformLocation =
                 gl.get_uniform_location(self.program, "rotation").unwrap();
+            let sul_projection: C::UniformLocation =
+                gl.get_uniform_location(self.program, "projection").unwrap();
+            let sul_field_of_view: C::UniformLocation = gl
+                .get_uniform_location(self.program, "field_of_view")
+                .unwrap();
+            let sul_heatmap: C::UniformLocation =
+                gl.get_uniform_location(self.program, "heatmap").unwrap();
+            let sul_reference_scale: C::UniformLocation = gl
+                .get_uniform_location(self.program, "reference_scale")
+                .unwrap();
+            let sul_viewport: C::UniformLocation =
+                gl.get_uniform_location(self.program, "viewport").unwrap();
+            let sul_layer_count: C::UniformLocation = gl
+                .get_uniform_location(self.program, "layer_count")
+                .unwrap();
+            let sul_layer_opacity: C::UniformLocation = gl
+                .get_uniform_location(self.program, "layer_opacity")
+                .unwrap();
+            let sul_layer_blend: C::UniformLocation = gl
+                .get_uniform_location(self.program, "layer_blend")
+                .unwrap();
 
             gl.use_program(Some(self.program));
-            gl.bind_texture(glow::TEXTURE_2D, Some(self.texture));
-            gl.uniform_1_i32(Some(&sul_world), 0);
+            let layers = &layers[..layers.len().min(MAX_LAYERS)];
+            let mut opacity = [0.0; MAX_LAYERS];
+            let mut blend = [0; MAX_LAYERS];
+            // layer0 on texture unit 0, and so on
+            for (i, layer) in layers.iter().enumerate() {
+                let sul_layer = gl.get_uniform_location(self.program, &format!("layer{i}"));
+                gl.active_texture(glow::TEXTURE0 + i as u32);
+                gl.bind_texture(glow::TEXTURE_2D, Some(layer.texture));
+                gl.uniform_1_i32(sul_layer.as_ref(), i as i32);
+                opacity[i] = layer.opacity;
+                blend[i] = layer.blend.shader_index();
+            }
+            // egui_glow expects to find unit 0 active
+            gl.active_texture(glow::TEXTURE0);
+            gl.uniform_1_i32(Some(&sul_layer_count), layers.len() as i32);
+            gl.uniform_1_f32_slice(Some(&sul_layer_opacity), &opacity);
+            gl.uniform_1_i32_slice(Some(&sul_layer_blend), &blend);
             gl.uniform_matrix_3_f32_slice(Some(&sul_matrix), false, rotation);
+            gl.uniform_1_i32(Some(&sul_projection), projection.shader_index());
+            gl.uniform_1_f32(
+                Some(&sul_field_of_view),
+                projection.field_of_view().unwrap_or(0.0),
+            );
+            gl.uniform_1_i32(Some(&sul_heatmap), heatmap.shader_index());
+            let (linear, areal) = reference_scale(&projection);
+            gl.uniform_2_f32(Some(&sul_reference_scale), linear, areal);
+            let [left, top, width, height] = viewport;
+            gl.uniform_4_f32(Some(&sul_viewport), left, top, width, height);
             self.vertex_array.bind(gl);
             gl.bind_vertex_array(Some(self.vertex_array.vertex_array));
             gl.draw_arrays(glow::TRIANGLE_STRIP, 0, 4);
         }
     }
+
+    /// render the map at `width` by `height` pixels into an offscreen framebuffer and read it
+    /// back as RGBA, top row first.  Images bigger than `GL_MAX_RENDERBUFFER_SIZE` are rendered
+    /// in tiles.  `overlay` is called in each tile once the map is drawn, with the tile's rect in
+    /// pixels of the whole image, to draw on top of the map.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn render_offscreen(
+        &self,
+        gl: &Arc<C>,
+        [width, height]: [u32; 2],
+        rotation: &[f32],
+        projection: Projection,
+        heatmap: DistortionHeatmap,
+        layers: &[ShaderLayer<C::Texture>],
+        viewport: &Viewport,
+        mut overlay: impl FnMut(Rect),
+    ) -> Result<Vec<u8>, String> {
+        unsafe {
+            let mut viewport_dims = [0; 2];
+            gl.get_parameter_i32_slice(glow::MAX_VIEWPORT_DIMS, &mut viewport_dims);
+            let max_size = gl
+                .get_parameter_i32(glow::MAX_RENDERBUFFER_SIZE)
+                .min(viewport_dims[0])
+                .min(viewport_dims[1])
+                .max(1) as u32;
+            let (tile_width, tile_height) = (width.min(max_size), height.min(max_size));
+
+            let framebuffer = gl.create_framebuffer()?;
+            gl.bind_framebuffer(glow::FRAMEBUFFER, Some(framebuffer));
+            let renderbuffer = gl.create_renderbuffer()?;
+            gl.bind_renderbuffer(glow::RENDERBUFFER, Some(renderbuffer));
+            gl.renderbuffer_storage(
+                glow::RENDERBUFFER,
+                glow::RGBA8,
+                tile_width as i32,
+                tile_height as i32,
+            );
+            gl.framebuffer_renderbuffer(
+                glow::FRAMEBUFFER,
+                glow::COLOR_ATTACHMENT0,
+                glow::RENDERBUFFER,
+                Some(renderbuffer),
+            );
+
+            let result =
+                if gl.check_framebuffer_status(glow::FRAMEBUFFER) != glow::FRAMEBUFFER_COMPLETE {
+                    Err(format!(
+                        "cannot render {tile_width}x{tile_height} offscreen (GL error {:#x})",
+                        gl.get_error()
+                    ))
+                } else {
+                    let size = Vec2::new(width as f32, height as f32);
+                    let (width, height) = (width as usize, height as usize);
+                    let mut pixels = vec![0; width * height * 4];
+                    let mut tile_pixels = vec![0; tile_width as usize * tile_height as usize * 4];
+                    for top in (0..height).step_by(tile_height as usize) {
+                        for left in (0..width).step_by(tile_width as usize) {
+                            let w = (tile_width as usize).min(width - left);
+                            let h = (tile_height as usize).min(height - top);
+                            let tile = Rect::from_min_size(
+                                Pos2::new(left as f32, top as f32),
+                                Vec2::new(w as f32, h as f32),
+                            );
+
+                            gl.viewport(0, 0, w as i32, h as i32);
+                            gl.disable(glow::SCISSOR_TEST);
+                            gl.disable(glow::BLEND);
+                            let part = Rect::from_min_max(
+                                (tile.min.to_vec2() / size).to_pos2(),
+                                (tile.max.to_vec2() / size).to_pos2(),
+                            );
+                            self.draw(
+                                gl,
+                                rotation,
+                                projection,
+                                heatmap,
+                                layers,
+                                viewport.uniform_for(part),
+                            );
+                            overlay(tile);
+
+                            gl.bind_framebuffer(glow::FRAMEBUFFER, Some(framebuffer));
+                            let tile_pixels = &mut tile_pixels[..w * h * 4];
+                            gl.read_pixels(
+                                0,
+                                0,
+                                w as i32,
+                                h as i32,
+                                glow::RGBA,
+                                glow::UNSIGNED_BYTE,
+                                glow::PixelPackData::Slice(tile_pixels),
+                            );
+                            // GL counts rows from the bottom
+                            for (row, source) in tile_pixels.chunks_exact(w * 4).rev().enumerate() {
+                                let start = ((top + row) * width + left) * 4;
+                                pixels[start..start + w * 4].copy_from_slice(source);
+                            }
+                        }
+                    }
+                    Ok(pixels)
+                };
+
+            gl.bind_framebuffer(glow::FRAMEBUFFER, None);
+            gl.bind_renderbuffer(glow::RENDERBUFFER, None);
+            gl.delete_renderbuffer(renderbuffer);
+            gl.delete_framebuffer(framebuffer);
+            result
+        }
+    }
 }
 
 impl<C: HasContext> Drop for WorldGLSL<C> {
     fn drop(&mut self) {
-        todo!()
+        // nothing to do without the GL context: the layer textures are freed by
+        // LayerTextures::destroy, and the program and buffers go with the context
+    }
+}
+
+//
+
+/// a layer as [`WorldGLSL`] draws it
+#[derive(Clone, Copy)]
+pub struct ShaderLayer<T> {
+    pub texture: T,
+    pub opacity: f32,
+    pub blend: BlendMode,
+}
+
+/// a texture for each layer of a [`crate::layers::LayerStack`], made when the layer first
+/// appears and deleted when it goes
+pub struct LayerTextures<C: HasContext> {
+    textures: Vec<(Arc<WorldSampler>, C::Texture)>,
+    /// the layers whose upload failed, not tried again until the layers change
+    failed: Vec<Arc<WorldSampler>>,
+    /// GL's MAX_TEXTURE_SIZE, asked for once
+    max_side: Option<u32>,
+}
+
+impl<C: HasContext> Default for LayerTextures<C> {
+    fn default() -> Self {
+        Self {
+            textures: vec![],
+            failed: vec![],
+            max_side: None,
+        }
+    }
+}
+
+impl<C: HasContext> LayerTextures<C> {
+    /// upload the images of new layers and delete the textures of removed ones.  A layer that
+    /// cannot be uploaded is left out of the map, and tried again once a layer is added or
+    /// removed.  Only a new failure is returned, so the error is reported once.
+    pub(crate) fn update(&mut self, gl: &Arc<C>, layers: &[ImageLayer]) -> Result<(), String> {
+        let present = |sampler: &Arc<WorldSampler>| {
+            layers
+                .iter()
+                .any(|layer| Arc::ptr_eq(&layer.sampler, sampler))
+        };
+        let added = layers
+            .iter()
+            .any(|layer| self.texture(layer).is_none() && !self.has_failed(layer));
+        let (kept, removed): (Vec<_>, Vec<_>) = std::mem::take(&mut self.textures)
+            .into_iter()
+            .partition(|(sampler, _)| present(sampler));
+        self.textures = kept;
+        let removed_failed = self.failed.iter().any(|sampler| !present(sampler));
+        if added || !removed.is_empty() || removed_failed {
+            self.failed.clear();
+        }
+        for (_, texture) in removed {
+            unsafe { gl.delete_texture(texture) };
+        }
+
+        let max_side = *self.max_side.get_or_insert_with(|| {
+            (unsafe { gl.get_parameter_i32(glow::MAX_TEXTURE_SIZE) }).max(1) as u32
+        });
+        let mut result = Ok(());
+        for layer in layers {
+            if self.has_failed(layer) || self.texture(layer).is_some() {
+                continue;
+            }
+            // too large an image is uploaded at the largest mipmap level that fits
+            let image = layer.sampler.level_within(max_side);
+            match unsafe { WorldGLSL::<C>::layer_texture(gl, image) } {
+                Ok(texture) => self.textures.push((layer.sampler.clone(), texture)),
+                Err(error) => {
+                    self.failed.push(layer.sampler.clone());
+                    result = Err(format!("no texture for layer {}: {error}", layer.name));
+                }
+            }
+        }
+        result
+    }
+
+    /// delete every texture, before the GL context goes away
+    pub(crate) fn destroy(&mut self, gl: &C) {
+        for (_, texture) in self.textures.drain(..) {
+            unsafe { gl.delete_texture(texture) };
+        }
+    }
+
+    fn texture(&self, layer: &ImageLayer) -> Option<C::Texture> {
+        self.textures
+            .iter()
+            .find(|(sampler, _)| Arc::ptr_eq(&layer.sampler, sampler))
+            .map(|(_, texture)| *texture)
+    }
+
+    fn has_failed(&self, layer: &ImageLayer) -> bool {
+        self.failed
+            .iter()
+            .any(|sampler| Arc::ptr_eq(&layer.sampler, sampler))
+    }
+
+    /// the layers that make a difference to the map, bottom first, for [`WorldGLSL::paint`]
+    pub(crate) fn shader_layers(&self, layers: &[ImageLayer]) -> Vec<ShaderLayer<C::Texture>> {
+        layers
+            .iter()
+            .filter(|layer| layer.shown())
+            .filter_map(|layer| {
+                Some(ShaderLayer {
+                    texture: self.texture(layer)?,
+                    opacity: layer.opacity,
+                    blend: layer.blend,
+                })
+            })
+            .collect()
     }
 }
 
@@ -172,7 +480,9 @@ impl<C: HasContext> Drop for WorldGLSL<C> {
 
 pub struct VertexBufferHolder<C: HasContext, T> {
     vertex_array: C::VertexArray,
+    #[allow(dead_code)]
     vbo: C::Buffer,
+    #[allow(dead_code)]
     payload: Vec<T>,
     // gl: Arc<C>,
 }
@@ -1,44 +1,87 @@
 use crate::background_image::BackgroundImage;
+use crate::coords::LonLat;
+use crate::geodesy::{route_info_ui, WaypointOptions};
+use crate::layers::{composite, ImageLayer, LayerStack};
+use crate::layout::{layout_combo_box, map_fraction, MapLayout};
+use crate::projection::{projection_combo_box, Projection};
 use crate::raw_image::RawImage;
-use crate::remapper::GreatCircleRemapper;
+use crate::remapper::{heading_fallback_ui, AnchorError, GreatCircleRemapper};
+use crate::tissot::TissotOptions;
 use eframe::emath::Vec2;
 use egui::{
-    Color32, ColorImage, Image, PointerButton, Pos2, Rect, Response, Sense, Shape, TextureHandle,
-    Ui, Widget,
+    Color32, ColorImage, Image, PointerButton, Pos2, Rect, Response, Sense, Shape, Stroke,
+    TextureHandle, Ui, Widget,
 };
 use std::io::Cursor;
 use std::mem;
 use std::sync::Arc;
 
+/// color of the parts of the widget that are outside the projected map (keep in sync with fragment.glsl)
+pub const OFF_MAP_RGBA: [u8; 4] = [0x20, 0x20, 0x28, 0xff];
+
 pub struct WorldSampler {
+    /// with premultiplied alpha, see [`RawImage::premultiplied`]
     pub raw_image: RawImage,
+    /// `raw_image` at half, quarter, ... size, down to a single row, like a GL mipmap chain
+    mipmaps: Vec<RawImage>,
 }
 
 impl WorldSampler {
-    fn new() -> WorldSampler {
-        let raw_image = Self::raw_world_map();
-        Self { raw_image }
+    /// the built-in world map
+    pub(crate) fn new() -> WorldSampler {
+        Self::from_image(Self::raw_world_map())
+    }
+
+    pub(crate) fn from_image(raw_image: RawImage) -> WorldSampler {
+        let raw_image = raw_image.premultiplied();
+        let mut mipmaps: Vec<RawImage> = vec![];
+        while mipmaps.last().unwrap_or(&raw_image).height > 1 {
+            mipmaps.push(mipmaps.last().unwrap_or(&raw_image).half_size());
+        }
+        Self { raw_image, mipmaps }
     }
 
     pub fn raw_world_map() -> RawImage {
         let raw = include_bytes!("world.png");
-        let decoder = png::Decoder::new(Cursor::new(raw));
+        RawImage::from_png(Cursor::new(raw)).unwrap()
+    }
 
-        let mut reader = decoder.read_info().unwrap();
-        // Allocate the output buffer.
-        let mut buf = vec![0; reader.output_buffer_size()];
-        // Read the next frame. An APNG might contain multiple frames.
-        let info = reader.next_frame(&mut buf).unwrap();
-        // Grab the bytes of the image.
-        buf.truncate(info.buffer_size());
+    /// the largest level of the mipmap chain that is no more than `max_side` pixels on a side
+    pub(crate) fn level_within(&self, max_side: u32) -> &RawImage {
+        std::iter::once(&self.raw_image)
+            .chain(&self.mipmaps)
+            .find(|image| image.width.max(image.height) <= max_side)
+            .unwrap_or(self.mipmaps.last().unwrap_or(&self.raw_image))
+    }
 
-        RawImage::new(info.width, info.height, buf)
+    /// the trilinear filtered color at `position`, for an output pixel that covers `footprint`
+    /// pixels of the full size image.  The same as `LINEAR_MIPMAP_LINEAR` in world2.rs.
+    /// The channels are fractions, alpha included, and the colors are premultiplied by alpha.
+    pub(crate) fn get(&self, position: LonLat, footprint: f32) -> [f32; 4] {
+        let uv = position.to_uv();
+        let lod = footprint.max(1.0).log2().min(self.mipmaps.len() as f32);
+        let level = lod.floor() as usize;
+        let image = |level: usize| match level {
+            0 => &self.raw_image,
+            _ => &self.mipmaps[(level - 1).min(self.mipmaps.len() - 1)],
+        };
+        let fine = image(level).bilinear(uv.x, uv.y);
+        let coarse = image(level + 1).bilinear(uv.x, uv.y);
+        let t = lod - level as f32;
+        [0, 1, 2, 3].map(|i| (fine[i] * (1.0 - t) + coarse[i] * t) / 255.0)
     }
 
-    pub(crate) fn get(&self, longitude_frac: f32, latitude_frac: f32) -> [u8; 4] {
-        let x = (longitude_frac * self.raw_image.width as f32) as usize;
-        let y = (latitude_frac * self.raw_image.height as f32) as usize;
-        self.raw_image.rgba_at(x, y)
+    /// how many pixels of the full size image lie between two positions that are one output
+    /// pixel apart, allowing for the wrap at the antimeridian
+    pub(crate) fn footprint(&self, from: LonLat, to: LonLat) -> f32 {
+        let (from, to) = (from.to_uv(), to.to_uv());
+        let du = to.x - from.x;
+        let du = du - du.round();
+        Vec2::new(
+            du * self.raw_image.width as f32,
+            (to.y - from.y) * self.raw_image.height as f32,
+        )
+        .length()
     }
 }
 
@@ -49,31 +92,98 @@ pub struct WorldMap {
     width: usize,
     height: usize,
 
-    anchors: Vec<Vec2>,
+    anchors: Vec<LonLat>,
     last_hover: Option<(f32, f32)>,
-    world_sampler: Arc<WorldSampler>,
+    layers: LayerStack,
     remapper: Arc<GreatCircleRemapper>,
+    projection: Projection,
+    /// set while the anchors do not pick out a great circle
+    anchor_error: Option<AnchorError>,
+    /// radians clockwise from north of the fallback great circle through the first anchor
+    heading: f32,
+    waypoint_options: WaypointOptions,
+    tissot: TissotOptions,
+    /// how the 2:1 map is placed in the widget
+    layout: MapLayout,
 }
 
 impl WorldMap {
     pub fn new(_cc: &eframe::CreationContext<'_>) -> Self {
         Self {
-            world_sampler: Arc::new(WorldSampler::new()),
+            layers: LayerStack::default(),
             texture: WorldMapCalculating::Nothing,
             width: 512,
             height: 512,
             anchors: vec![],
             last_hover: None,
-            remapper: Arc::new(GreatCircleRemapper::new(&[])),
+            remapper: Arc::new(
+                GreatCircleRemapper::new(&[], Projection::default())
+                    .expect("no anchors is never degenerate"),
+            ),
+            projection: Projection::default(),
+            anchor_error: None,
+            heading: 90.0_f32.to_radians(),
+            waypoint_options: WaypointOptions::default(),
+            tissot: TissotOptions::default(),
+            layout: MapLayout::default(),
         }
     }
 
-    pub(crate) fn set_anchor(&mut self, longitude_frac: f32, latitude_frac: f32) {
-        self.anchors.push(Vec2::new(longitude_frac, latitude_frac));
+    pub(crate) fn set_anchor(&mut self, position: LonLat) {
+        self.anchors.push(position);
         while self.anchors.len() > 2 {
             self.anchors.remove(0);
         }
-        self.remapper = Arc::new(GreatCircleRemapper::new(&self.anchors));
+        self.update_remapper();
+    }
+
+    /// rebuild the remapper from the anchors, falling back to `heading` if they are degenerate
+    fn update_remapper(&mut self) {
+        let remapper = match GreatCircleRemapper::new(&self.anchors, self.projection) {
+            Ok(remapper) => {
+                self.anchor_error = None;
+                remapper
+            }
+            Err(error) => {
+                self.anchor_error = Some(error);
+                GreatCircleRemapper::from_heading(self.anchors[0], self.heading, self.projection)
+            }
+        };
+        self.remapper = Arc::new(remapper);
+    }
+
+    pub fn layout(&self) -> MapLayout {
+        self.layout
+    }
+
+    pub fn set_layout(&mut self, layout: MapLayout) {
+        self.layout = layout;
+    }
+
+    pub fn route_info_ui(&mut self, ui: &mut Ui) {
+        route_info_ui(ui, &self.anchors, &mut self.waypoint_options);
+    }
+
+    pub fn controls_ui(&mut self, ui: &mut Ui) {
+        let old = self.projection;
+        projection_combo_box(ui, &mut self.projection);
+        let mut changed = old != self.projection;
+        let old_layout = self.layout;
+        layout_combo_box(ui, &mut self.layout);
+        // the image is calculated at the size of the map rect
+        changed |= old_layout != self.layout;
+        if let Some(error) = self.anchor_error {
+            changed |= heading_fallback_ui(ui, error, &mut self.heading);
+        }
+        self.tissot.ui(ui);
+        changed |= ui
+            .menu_button("layers", |ui| self.layers.ui(ui))
+            .inner
+            .unwrap_or(false);
+        if changed {
+            self.update_remapper();
+            self.calculate_replacement_image(ui);
+        }
     }
 
     fn get_texture(&mut self, ui: &mut Ui) -> Option<TextureHandle> {
@@ -81,7 +191,7 @@ impl WorldMap {
             ui,
             self.width,
             self.height,
-            self.world_sampler.clone(),
+            self.layers.layers.clone(),
             self.remapper.clone(),
         )
     }
@@ -91,7 +201,7 @@ impl WorldMap {
             ui,
             self.width,
             self.height,
-            self.world_sampler.clone(),
+            self.layers.layers.clone(),
             self.remapper.clone(),
         );
         self.texture.start_recalculating(image_pipe);
@@ -100,12 +210,10 @@ impl WorldMap {
 
 impl Widget for &mut WorldMap {
     fn ui(self, ui: &mut Ui) -> Response {
-        self.width = ui.available_width() as _;
-        self.height = ui.available_height() as _;
-        let response = ui.allocate_response(
-            Vec2::new(self.width as f32, self.height as f32),
-            Sense::click(),
-        );
+        let response = ui.allocate_response(ui.available_size(), Sense::click());
+        let map_rect = self.layout.map_rect(response.rect);
+        self.width = map_rect.width() as _;
+        self.height = map_rect.height() as _;
 
         /*   println!(
             "widthxheight {}x{}",
@@ -115,8 +223,7 @@ impl Widget for &mut WorldMap {
 
         if let Some(pos) = response.hover_pos() {
             // println!("hover {:?}", pos);
-            let Vec2 { x, y } = pos - response.rect.left_top();
-            self.last_hover = Some((x / response.rect.width(), y / response.rect.height()));
+            self.last_hover = map_fraction(&map_rect, pos).map(|uv| (uv.x, uv.y));
         }
 
         if response.clicked() {
@@ -124,37 +231,39 @@ impl Widget for &mut WorldMap {
                 let Vec2 { x, y } = pos - response.rect.left_top();
                 println!("click {},{}", x, y);
 
-                let u = x / response.rect.width();
-                let v = y / response.rect.height();
-                let Vec2 { x: u, y: v } = self.remapper.untwist(u, v);
+                let uv = map_fraction(&map_rect, pos);
+                if let Some(position) = uv.and_then(|uv| self.remapper.untwist_vec2(uv)) {
+                    self.set_anchor(position);
 
-                self.set_anchor(u, v);
-
-                self.calculate_replacement_image(ui);
+                    self.calculate_replacement_image(ui);
+                }
             }
         }
 
-        let rect = &response.rect;
+        let rect = &map_rect;
+        // the map rect sticks out of the widget for MapLayout::Fill
+        let painter = ui.painter_at(response.rect);
+        // the bars of MapLayout::Fit
+        let [r, g, b, _] = OFF_MAP_RGBA;
+        painter.rect_filled(response.rect, 0.0, Color32::from_rgb(r, g, b));
 
         // println!("enabled? {}", ui.is_enabled());
 
         #[allow(clippy::single_match)]
         match self.get_texture(ui) {
             Some(texture) => {
-                let texture_size = texture.size_vec2();
+                // stretched over the map rect while a resized image is being calculated
                 match 2 {
                     1 => {
-                        let rect = Rect::from_min_max(rect.min, rect.min + texture_size);
                         let img = Image::new(&texture);
-                        img.paint_at(ui, rect);
+                        img.paint_at(ui, *rect);
                     }
                     _ => {
-                        let rect = Rect::from_min_max(rect.min, rect.min + texture_size);
                         let full_square =
                             Rect::from_min_max(Pos2::new(0.0, 0.0), Pos2::new(1.0, 1.0));
-                        ui.painter().add(Shape::image(
+                        painter.add(Shape::image(
                             texture.id(),
-                            rect,
+                            *rect,
                             full_square,
                             Color32::from_rgb(0xff, 0xff, 0xff),
                         ));
@@ -164,12 +273,33 @@ impl Widget for &mut WorldMap {
             None => {}
         }
 
+        let stroke = Stroke::new(1.0, Color32::from_rgb(0xff, 0x80, 0x40));
+        for outline in self
+            .tissot
+            .outlines(&self.remapper.projection, &self.remapper.matrix)
+        {
+            let points = outline.into_iter().map(|uv| rect.min + uv * rect.size());
+            painter.add(Shape::closed_line(points.collect(), stroke));
+        }
+
+        for waypoint in self.waypoint_options.markers(&self.anchors) {
+            let Some(Vec2 { x: u, y: v }) = self.remapper.twist_vec2(waypoint) else {
+                continue;
+            };
+
+            let xy = Vec2::new(u * rect.width(), v * rect.height());
+            let stroke = Stroke::new(1.5, Color32::WHITE);
+            painter.add(Shape::circle_stroke(rect.min + xy, 4.0, stroke));
+        }
+
         for anchor in &self.anchors {
-            let Vec2 { x: u, y: v } = self.remapper.twist(anchor.x, anchor.y);
+            let Some(Vec2 { x: u, y: v }) = self.remapper.twist_vec2(*anchor) else {
+                continue;
+            };
 
-            let xy = Vec2::new(u * response.rect.width(), v * response.rect.height());
+            let xy = Vec2::new(u * rect.width(), v * rect.height());
             let circle = Shape::circle_filled(rect.min + xy, 3.0, Color32::from_rgb(0xff, 0, 0));
-            ui.painter().add(circle);
+            painter.add(circle);
         }
 
         if false {
@@ -214,16 +344,11 @@ impl WorldMapCalculating {
         ui: &mut Ui,
         width: usize,
         height: usize,
-        world_sampler: Arc<WorldSampler>,
+        layers: Vec<ImageLayer>,
         remapper: Arc<GreatCircleRemapper>,
     ) -> Option<TextureHandle> {
-        let (new_val, rval) = mem::replace(self, Self::Nothing).inner_get_texture(
-            ui,
-            width,
-            height,
-            world_sampler,
-            remapper,
-        );
+        let (new_val, rval) = mem::replace(self, Self::Nothing)
+            .inner_get_texture(ui, width, height, layers, remapper);
         *self = new_val;
         rval
     }
@@ -233,12 +358,12 @@ impl WorldMapCalculating {
         ui: &mut Ui,
         width: usize,
         height: usize,
-        world_sampler: Arc<WorldSampler>,
+        layers: Vec<ImageLayer>,
         remapper: Arc<GreatCircleRemapper>,
     ) -> (WorldMapCalculating, Option<TextureHandle>) {
         match self {
             WorldMapCalculating::Nothing => {
-                let image_pipe = BackgroundImage::new(ui, width, height, world_sampler, remapper);
+                let image_pipe = BackgroundImage::new(ui, width, height, layers, remapper);
                 (WorldMapCalculating::CalculatingNoTexture(image_pipe), None)
             }
             WorldMapCalculating::CalculatingNoTexture(mut image_pipe) => match image_pipe.get(ui) {
@@ -282,7 +407,7 @@ impl WorldMapCalculating {
 pub fn world_map(
     width: usize,
     height: usize,
-    world_sampler: &WorldSampler,
+    layers: &[ImageLayer],
     remapper: &GreatCircleRemapper,
 ) -> ColorImage {
     println!("calculating new world map image");
@@ -290,13 +415,24 @@ pub fn world_map(
         let u0 = col as f32 / width as f32;
         let v0 = row as f32 / height as f32;
 
-        let Vec2 { x: u, y: v } = remapper.untwist(u0, v0);
+        let Some(position) = remapper.untwist_vec2(Vec2::new(u0, v0)) else {
+            return OFF_MAP_RGBA;
+        };
 
         if col == 0 && row == 50 {
-            println!("{},{} -> {},{}", u0, v0, u, v)
+            println!("{},{} -> {:?}", u0, v0, position)
         }
 
-        world_sampler.get(u, v)
+        // the neighbouring pixels tell how much of the world this one covers
+        let neighbours: Vec<LonLat> = [
+            Vec2::new(1.0 / width as f32, 0.0),
+            Vec2::new(0.0, 1.0 / height as f32),
+        ]
+        .into_iter()
+        .filter_map(|step| remapper.untwist_vec2(Vec2::new(u0, v0) + step))
+        .collect();
+
+        composite(layers, position, &neighbours)
     })
     .collect();
 
@@ -1,9 +1,31 @@
-use crate::remapper::{transform_ll_to_ll, GreatCircleRemapper};
-use crate::world2::WorldGLSL;
-use cgmath::{Matrix3, SquareMatrix};
+use crate::animation::Transition;
+use crate::coords::{LonLat, UnitVector};
+use crate::distortion::{heatmap_combo_box, legend_shapes, DistortionHeatmap, ScaleFactors};
+use crate::ellipsoid::{EllipsoidalRoute, EARTH_MEAN_RADIUS_M};
+use crate::export::{save_png, ExportOptions, OverlayPainter};
+use crate::geodesy::{route_info_ui, RhumbLine, WaypointOptions};
+use crate::graticule::{edge_points, label_shapes, refine, GraticuleOptions};
+use crate::layers::LayerStack;
+use crate::layout::{layout_combo_box, map_fraction, MapLayout};
+use crate::oblique::ObliquePole;
+use crate::projection::{projection_combo_box, Projection};
+use crate::remapper::{
+    best_fit_matrix, great_circle_arc, heading_fallback_ui, matrix_from_anchors,
+    matrix_from_heading, roll_to_center, to_vec2, AnchorError,
+};
+use crate::tissot::TissotOptions;
+use crate::viewport::Viewport;
+use crate::world2::{LayerTextures, WorldGLSL};
+use crate::world_map::OFF_MAP_RGBA;
+use cgmath::{Matrix, Matrix3, Rad, SquareMatrix, Vector3};
 use eframe::emath::Vec2;
 use eframe::glow::Context;
-use egui::{Color32, PaintCallback, PointerButton, Response, Sense, Shape, Ui, Widget};
+use egui::text::Fonts;
+use egui::{
+    Color32, PaintCallback, PointerButton, Pos2, Rect, Response, Sense, Shape, Stroke, Ui, Widget,
+};
+use std::collections::VecDeque;
+use std::f32::consts::{PI, TAU};
 use std::sync::Arc;
 
 //
@@ -12,11 +34,72 @@ pub struct WorldMap2 {
     width: usize,
     height: usize,
 
-    anchors: Vec<Vec2>,
-    last_hover: Option<(f32, f32)>,
+    anchors: Vec<LonLat>,
+    /// the point of the source ERP under the pointer
+    last_hover: Option<LonLat>,
     world2: Arc<WorldGLSL<Context>>,
+    /// for rendering exported images outside of a paint callback
+    gl: Arc<Context>,
     matrix: Matrix3<f32>,
-    matrix_inverse: Matrix3<f32>,
+    projection: Projection,
+    /// radians to slide the view along the chosen great circle, on top of `matrix`
+    roll: f32,
+    /// also draw the WGS84 geodesic between the anchors
+    show_geodesic: bool,
+    /// the WGS84 geodesic between the first two anchors, while `show_geodesic` is on
+    geodesic: Option<EllipsoidalRoute>,
+    /// also draw the rhumb line between the anchors
+    show_rhumb_line: bool,
+    /// the rhumb line between the first two anchors, while `show_rhumb_line` is on
+    rhumb_line: Option<RhumbLine>,
+    /// keep every anchor and fit the great circle to all of them
+    best_fit: bool,
+    /// RMS distance in radians of the anchors from the fitted great circle
+    fit_rms: Option<f32>,
+    /// set while the anchors do not pick out a great circle
+    anchor_error: Option<AnchorError>,
+    /// radians clockwise from north of the fallback great circle through the first anchor
+    heading: f32,
+    waypoint_options: WaypointOptions,
+    tissot: TissotOptions,
+    graticule: GraticuleOptions,
+    heatmap: DistortionHeatmap,
+    /// the imagery, composited by the shader
+    layers: LayerStack,
+    layer_textures: LayerTextures<Context>,
+    /// how the 2:1 map is placed in the widget
+    layout: MapLayout,
+    /// the part of the projected map that the widget shows
+    viewport: Viewport,
+    /// where the whole map was laid out in the last frame
+    map_rect: Rect,
+    export: ExportOptions,
+    /// a PROJ `ob_tran` string typed in by the user, waiting to be applied
+    proj_text: String,
+    /// why `proj_text` could not be applied
+    proj_error: Option<String>,
+    /// the view matrix of the last frame drawn, where the next transition starts from
+    shown_matrix: Matrix3<f32>,
+    /// the animation towards the current view, if one is running
+    transition: Option<Transition>,
+    /// how long transitions take.  Zero snaps straight to the new view.
+    transition_seconds: f32,
+    /// `egui::InputState::time` of the current frame
+    now: f64,
+    saved_views: Vec<SavedView>,
+    /// views still to visit in a fly-through
+    tour: VecDeque<SavedView>,
+}
+
+/// enough of the state of a [`WorldMap2`] to return to a view
+#[derive(Clone)]
+pub struct SavedView {
+    anchors: Vec<LonLat>,
+    matrix: Matrix3<f32>,
+    roll: f32,
+    /// how `matrix` was found from `anchors`, as shown beside the map
+    anchor_error: Option<AnchorError>,
+    fit_rms: Option<f32>,
 }
 
 impl WorldMap2 {
@@ -30,22 +113,616 @@ impl WorldMap2 {
             anchors: vec![],
             last_hover: None,
             world2,
+            gl: gl.clone(),
             matrix,
-            matrix_inverse: matrix,
+            projection: Projection::default(),
+            roll: 0.0,
+            show_geodesic: false,
+            geodesic: None,
+            show_rhumb_line: false,
+            rhumb_line: None,
+            best_fit: false,
+            fit_rms: None,
+            anchor_error: None,
+            heading: 90.0_f32.to_radians(),
+            waypoint_options: WaypointOptions::default(),
+            tissot: TissotOptions::default(),
+            graticule: GraticuleOptions::default(),
+            heatmap: DistortionHeatmap::default(),
+            layers: LayerStack::default(),
+            layer_textures: LayerTextures::default(),
+            layout: MapLayout::default(),
+            viewport: Viewport::default(),
+            map_rect: Rect::NOTHING,
+            export: ExportOptions::default(),
+            proj_text: String::new(),
+            proj_error: None,
+            shown_matrix: matrix,
+            transition: None,
+            transition_seconds: 1.0,
+            now: 0.0,
+            saved_views: vec![],
+            tour: VecDeque::new(),
         }
     }
 
-    pub(crate) fn set_anchor(&mut self, longitude_frac: f32, latitude_frac: f32) {
-        self.anchors.push(Vec2::new(longitude_frac, latitude_frac));
-        while self.anchors.len() > 2 {
+    pub(crate) fn set_anchor(&mut self, position: LonLat) {
+        self.anchors.push(position);
+        self.trim_anchors();
+        self.update_matrix();
+        self.update_overlays();
+    }
+
+    fn trim_anchors(&mut self) {
+        let keep = if self.best_fit { usize::MAX } else { 2 };
+        while self.anchors.len() > keep {
             self.anchors.remove(0);
         }
-        self.set_matrix(GreatCircleRemapper::matrix_from_anchors(&self.anchors));
     }
 
+    /// rebuild the matrix from the anchors, falling back to `heading` if they are degenerate
+    fn update_matrix(&mut self) {
+        self.fit_rms = None;
+        let matrix = if self.best_fit && self.anchors.len() > 1 {
+            best_fit_matrix(&self.anchors).map(|(matrix, rms)| {
+                self.fit_rms = Some(rms);
+                matrix
+            })
+        } else {
+            matrix_from_anchors(&self.anchors)
+        };
+        let matrix = match matrix {
+            Ok(matrix) => {
+                self.anchor_error = None;
+                matrix
+            }
+            Err(AnchorError::NoUniqueFit) => {
+                // no heading would be any better, so keep the view until the anchors change
+                self.anchor_error = Some(AnchorError::NoUniqueFit);
+                return;
+            }
+            Err(error) => {
+                self.anchor_error = Some(error);
+                matrix_from_heading(self.anchors[0], self.heading)
+            }
+        };
+        self.set_matrix(matrix);
+    }
+
+    /// recalculate the optional routes that are drawn alongside the great circle
+    fn update_overlays(&mut self) {
+        self.geodesic = match self.anchors[..] {
+            [anchor1, anchor2, ..] if self.show_geodesic => {
+                Some(EllipsoidalRoute::between(anchor1, anchor2, 200))
+            }
+            _ => None,
+        };
+        self.rhumb_line = match self.anchors[..] {
+            [anchor1, anchor2, ..] if self.show_rhumb_line => {
+                Some(RhumbLine::between(anchor1, anchor2, 200))
+            }
+            _ => None,
+        };
+    }
+
+    /// change the rotation, animating from the current view
     pub fn set_matrix(&mut self, matrix: Matrix3<f32>) {
         self.matrix = matrix;
-        self.matrix_inverse = matrix.invert().unwrap();
+        self.start_transition();
+    }
+
+    /// animate from what is on screen now to whatever the view becomes
+    fn start_transition(&mut self) {
+        self.transition = (self.transition_seconds > 0.0)
+            .then(|| Transition::new(self.shown_matrix, self.now, self.transition_seconds));
+    }
+
+    pub fn save_view(&self) -> SavedView {
+        SavedView {
+            anchors: self.anchors.clone(),
+            matrix: self.matrix,
+            roll: self.roll,
+            anchor_error: self.anchor_error,
+            fit_rms: self.fit_rms,
+        }
+    }
+
+    /// animate to a view from [`Self::save_view`]
+    pub fn go_to_view(&mut self, view: &SavedView) {
+        self.anchors = view.anchors.clone();
+        self.anchor_error = view.anchor_error;
+        self.fit_rms = view.fit_rms;
+        self.roll = view.roll;
+        self.set_matrix(view.matrix);
+        self.update_overlays();
+    }
+
+    /// animate through each of `views` in turn
+    pub fn fly_through(&mut self, views: impl IntoIterator<Item = SavedView>) {
+        self.tour = views.into_iter().collect();
+        self.advance_tour();
+    }
+
+    fn advance_tour(&mut self) {
+        if let Some(view) = self.tour.pop_front() {
+            self.go_to_view(&view);
+        }
+    }
+
+    /// show the view described by an oblique pole.  The anchors are dropped,
+    /// since the rotation no longer comes from them.
+    pub fn set_oblique_pole(&mut self, pole: &ObliquePole) {
+        self.anchors.clear();
+        self.anchor_error = None;
+        self.fit_rms = None;
+        self.roll = 0.0;
+        self.set_matrix(pole.matrix());
+        self.update_overlays();
+    }
+
+    /// radians to slide the view along the chosen great circle
+    pub fn set_roll(&mut self, roll: f32) {
+        self.roll = (roll + PI).rem_euclid(TAU) - PI;
+    }
+
+    /// the roll that puts the first anchor `longitude` radians east of the center of the view
+    fn roll_for_first_anchor(&self, longitude: f32) -> Option<f32> {
+        let anchor = self.anchors.first()?;
+        let rotated = (self.base_matrix().transpose() * anchor.to_unit_vector()).to_lon_lat();
+        Some(rotated.longitude - longitude)
+    }
+
+    pub fn layout(&self) -> MapLayout {
+        self.layout
+    }
+
+    pub fn set_layout(&mut self, layout: MapLayout) {
+        self.layout = layout;
+    }
+
+    /// free the GPU resources of the layers, before the GL context goes away
+    pub fn destroy(&mut self) {
+        self.layer_textures.destroy(&self.gl);
+    }
+
+    pub fn route_info_ui(&mut self, ui: &mut Ui) {
+        route_info_ui(ui, &self.anchors, &mut self.waypoint_options);
+    }
+
+    pub fn controls_ui(&mut self, ui: &mut Ui) {
+        let old = self.projection;
+        projection_combo_box(ui, &mut self.projection);
+        layout_combo_box(ui, &mut self.layout);
+        if old.centered_on_first_anchor() != self.projection.centered_on_first_anchor() {
+            // the view center moves to or from the first anchor
+            self.start_transition();
+        }
+
+        if let Some(error) = self.anchor_error {
+            if heading_fallback_ui(ui, error, &mut self.heading) {
+                self.update_matrix();
+            }
+        }
+
+        let mut degrees = self.roll.to_degrees();
+        let roll_response = ui.add(
+            egui::DragValue::new(&mut degrees)
+                .speed(0.5)
+                .suffix("°")
+                .prefix("offset "),
+        );
+        if roll_response.changed() {
+            self.set_roll(degrees.to_radians());
+        }
+        if ui.button("first anchor at center").clicked() {
+            if let Some(roll) = self.roll_for_first_anchor(0.0) {
+                self.set_roll(roll);
+                self.start_transition();
+            }
+        }
+        if ui.button("first anchor at left").clicked() {
+            // leave a little room so the marker is not cut in half by the edge
+            if let Some(roll) = self.roll_for_first_anchor(-175.0_f32.to_radians()) {
+                self.set_roll(roll);
+                self.start_transition();
+            }
+        }
+
+        ui.menu_button("PROJ", |ui| self.proj_ui(ui));
+        ui.menu_button("views", |ui| self.views_ui(ui));
+        // the textures are brought up to date before the next paint
+        ui.menu_button("layers", |ui| self.layers.ui(ui));
+        let export = ui.menu_button("Export image…", |ui| self.export.ui(ui));
+        if export.inner == Some(true) {
+            self.export.status = Some(self.export_image(ui.ctx()));
+        }
+
+        if ui
+            .checkbox(&mut self.best_fit, "best fit")
+            .on_hover_text("fit the great circle to every anchor instead of the last two")
+            .changed()
+        {
+            self.trim_anchors();
+            self.update_matrix();
+            self.update_overlays();
+        }
+        if self.best_fit {
+            if ui.button("clear anchors").clicked() {
+                self.anchors.clear();
+                self.update_matrix();
+                self.update_overlays();
+            }
+            if let Some(rms) = self.fit_rms {
+                ui.label(format!(
+                    "{} anchors, RMS cross-track {:.1} km",
+                    self.anchors.len(),
+                    rms as f64 * EARTH_MEAN_RADIUS_M / 1000.0
+                ));
+            }
+        }
+
+        if ui
+            .checkbox(&mut self.show_geodesic, "WGS84 geodesic")
+            .changed()
+        {
+            self.update_overlays();
+        }
+        if let Some(geodesic) = &self.geodesic {
+            ui.label(format!(
+                "geodesic {:.3} km, sphere {:.3} km, apart by up to {:.3} km",
+                geodesic.length_m / 1000.0,
+                geodesic.spherical_length_m / 1000.0,
+                geodesic.max_deviation_m / 1000.0,
+            ));
+        }
+
+        if ui
+            .checkbox(&mut self.show_rhumb_line, "rhumb line")
+            .changed()
+        {
+            self.update_overlays();
+        }
+        if let Some(rhumb_line) = self.rhumb_line.as_ref().filter(|r| r.great_circle_m > 0.0) {
+            ui.label(format!(
+                "rhumb line {:.1} km at {:.1}°, {:+.2}% on the great circle",
+                rhumb_line.length_m / 1000.0,
+                rhumb_line.bearing,
+                (rhumb_line.length_m / rhumb_line.great_circle_m - 1.0) * 100.0,
+            ));
+        }
+
+        self.tissot.ui(ui);
+        self.graticule.ui(ui);
+        heatmap_combo_box(ui, &mut self.heatmap);
+
+        if self.viewport.is_zoomed() {
+            ui.label(format!("×{:.1}", self.viewport.zoom));
+            if ui.button("reset zoom").clicked() {
+                self.viewport = Viewport::default();
+            }
+        }
+
+        if let Some(hover) = self.last_hover {
+            ui.label(format!(
+                "{:.2}°, {:.2}°",
+                hover.longitude_degrees(),
+                hover.latitude_degrees()
+            ));
+            let scale_factors = ScaleFactors::at(&self.projection, &self.view_matrix(), hover);
+            if let Some(scale_factors) =
+                scale_factors.filter(|_| self.heatmap != DistortionHeatmap::Off)
+            {
+                ui.label(format!(
+                    "scale ×{:.4} to ×{:.4}, area ×{:.4}",
+                    scale_factors.linear_min, scale_factors.linear_max, scale_factors.areal
+                ));
+            }
+        }
+    }
+
+    /// saved views, fly-throughs and how long the transitions take
+    fn views_ui(&mut self, ui: &mut Ui) {
+        ui.add(
+            egui::Slider::new(&mut self.transition_seconds, 0.0..=5.0)
+                .text("transition")
+                .suffix(" s"),
+        );
+        if ui.button("save view").clicked() {
+            self.saved_views.push(self.save_view());
+        }
+        let mut chosen = None;
+        for (i, view) in self.saved_views.iter().enumerate() {
+            if ui.button(format!("view {}", i + 1)).clicked() {
+                chosen = Some(view.clone());
+            }
+        }
+        if let Some(view) = chosen {
+            self.tour.clear();
+            self.go_to_view(&view);
+        }
+        if ui
+            .add_enabled(self.saved_views.len() > 1, egui::Button::new("fly through"))
+            .clicked()
+        {
+            self.fly_through(self.saved_views.clone());
+        }
+        if ui
+            .add_enabled(
+                !self.saved_views.is_empty(),
+                egui::Button::new("forget views"),
+            )
+            .clicked()
+        {
+            self.saved_views.clear();
+            self.tour.clear();
+        }
+    }
+
+    /// the current view as an oblique pole and a PROJ string, and a way to enter one
+    fn proj_ui(&mut self, ui: &mut Ui) {
+        let pole = ObliquePole::from_matrix(self.target_matrix());
+        let proj_string = pole.to_proj_string(self.projection);
+        ui.label(pole.to_string());
+        ui.label(&proj_string);
+        if ui.button("copy").clicked() {
+            ui.output_mut(|output| output.copied_text = proj_string);
+        }
+
+        ui.separator();
+        ui.text_edit_singleline(&mut self.proj_text);
+        if ui.button("apply").clicked() {
+            match ObliquePole::from_proj_string(&self.proj_text) {
+                Ok((pole, projection)) => {
+                    self.proj_error = None;
+                    if let Some(projection) = projection {
+                        // keep settings like the field of view if the projection is unchanged
+                        if projection.shader_index() != self.projection.shader_index() {
+                            self.projection = projection;
+                        }
+                    }
+                    self.set_oblique_pole(&pole);
+                }
+                Err(error) => self.proj_error = Some(error.to_string()),
+            }
+        }
+        if let Some(error) = &self.proj_error {
+            ui.colored_label(ui.visuals().error_fg_color, error);
+        }
+    }
+
+    /// the anchor-derived matrix, adjusted for the needs of the current projection
+    fn base_matrix(&self) -> Matrix3<f32> {
+        match self.anchors.first() {
+            Some(anchor) if self.projection.centered_on_first_anchor() => {
+                roll_to_center(self.matrix, *anchor)
+            }
+            _ => self.matrix,
+        }
+    }
+
+    /// [`Self::base_matrix`] slid along the great circle by `roll`
+    fn target_matrix(&self) -> Matrix3<f32> {
+        self.base_matrix() * Matrix3::from_angle_z(Rad(self.roll))
+    }
+
+    /// [`Self::target_matrix`], or part of the way there while a transition is running
+    fn view_matrix(&self) -> Matrix3<f32> {
+        match &self.transition {
+            Some(transition) => transition.matrix(self.target_matrix(), self.now),
+            None => self.target_matrix(),
+        }
+    }
+
+    /// the point of the source ERP under the (u,v) fraction of the map rect (see [`MapLayout`])
+    fn untwist(&self, u: f32, v: f32) -> Option<LonLat> {
+        let map = self.viewport.to_map(Vec2::new(u, v));
+        let xyz = self.projection.unproject(map.x, map.y)?;
+        Some(UnitVector::new(self.view_matrix() * xyz).to_lon_lat())
+    }
+
+    /// the (u,v) fraction of the map rect where a point of the source ERP is drawn
+    fn twist(&self, position: LonLat) -> Option<Vec2> {
+        self.twist_cartesian(position.to_unit_vector())
+            .map(|uv| self.viewport.to_widget(uv))
+    }
+
+    /// the (u,v) fraction of the whole projected map, before the viewport, where a point of the
+    /// unrotated sphere is drawn
+    fn twist_cartesian(&self, xyz: UnitVector) -> Option<Vec2> {
+        self.projection
+            .project((self.view_matrix().transpose() * xyz).vector())
+            .map(to_vec2)
+    }
+
+    /// the great circle arc between the first two anchors, and the other routes if enabled
+    fn route_shapes(&self, rect: &Rect, shapes: &mut Vec<Shape>) {
+        let [anchor1, anchor2, ..] = self.anchors[..] else {
+            return;
+        };
+        let arc = match self.anchor_error {
+            None | Some(AnchorError::NoUniqueFit) => {
+                great_circle_arc(anchor1.to_unit_vector(), anchor2.to_unit_vector(), 200)
+            }
+            // every half of a great circle through the first anchor reaches the second,
+            // so follow the chosen heading
+            Some(AnchorError::Antipodal) => (0..200)
+                .map(|i| {
+                    let t = PI * i as f32 / 199.0;
+                    UnitVector::new(self.matrix * Vector3::new(t.cos(), t.sin(), 0.0))
+                })
+                .collect(),
+            Some(AnchorError::Coincident) => vec![],
+        };
+        let stroke = Stroke::new(2.0, Color32::from_rgb(0xff, 0xcc, 0));
+        self.polyline_shapes(rect, arc.into_iter(), stroke, shapes);
+
+        // the rest of the legs of a best fit itinerary
+        for leg in self.anchors[1..].windows(2) {
+            let arc = great_circle_arc(leg[0].to_unit_vector(), leg[1].to_unit_vector(), 100);
+            self.polyline_shapes(rect, arc.into_iter(), stroke, shapes);
+        }
+
+        if let Some(geodesic) = &self.geodesic {
+            let points = geodesic.points.iter().map(|p| p.cast().to_unit_vector());
+            let stroke = Stroke::new(1.5, Color32::from_rgb(0x40, 0xe0, 0xff));
+            self.polyline_shapes(rect, points, stroke, shapes);
+        }
+
+        if let Some(rhumb_line) = &self.rhumb_line {
+            let points = rhumb_line.points.iter().map(|p| p.cast().to_unit_vector());
+            let stroke = Stroke::new(1.5, Color32::from_rgb(0xff, 0x60, 0xc0));
+            self.polyline_shapes(rect, points, stroke, shapes);
+        }
+    }
+
+    /// a line through points of the unrotated sphere, broken where it leaves the map.
+    /// `rect` is the map rect.
+    fn polyline_shapes(
+        &self,
+        rect: &Rect,
+        points: impl Iterator<Item = UnitVector>,
+        stroke: Stroke,
+        shapes: &mut Vec<Shape>,
+    ) {
+        for segment in split_polyline(points.map(|xyz| self.twist_cartesian(xyz))) {
+            let points = segment
+                .into_iter()
+                .map(|uv| rect.min + self.viewport.to_widget(uv) * rect.size())
+                .collect();
+            shapes.push(Shape::line(points, stroke));
+        }
+    }
+
+    /// the meridians and parallels of the unrotated world, labelled where they meet the edges of
+    /// the visible map
+    fn graticule_shapes(&self, fonts: &Fonts, rect: &Rect, widget: &Rect, shapes: &mut Vec<Shape>) {
+        let visible = widget.intersect(*rect);
+        let to_screen = |uv: Vec2| rect.min + self.viewport.to_widget(uv) * rect.size();
+        let mut labels = vec![];
+        let mut labelled: Vec<Rect> = vec![];
+        for line in self.graticule.lines() {
+            // smooth to within a few points of the screen however far the map is zoomed in
+            let points = refine(
+                &line.points,
+                |p| self.twist_cartesian(p.to_unit_vector()).map(to_screen),
+                &visible,
+                4.0,
+            );
+            let projected = points
+                .iter()
+                .map(|p| self.twist_cartesian(p.to_unit_vector()));
+            let segments: Vec<Vec<Pos2>> = split_polyline(projected)
+                .into_iter()
+                .map(|segment| segment.into_iter().map(to_screen).collect())
+                .collect();
+            let stroke = line.kind.stroke();
+            for segment in &segments {
+                if line.kind.dashed() {
+                    shapes.extend(Shape::dashed_line(segment, stroke, 6.0, 4.0));
+                } else {
+                    shapes.push(Shape::line(segment.clone(), stroke));
+                }
+            }
+
+            let line_ends: Vec<Pos2> = [line.points.first(), line.points.last()]
+                .into_iter()
+                .flatten()
+                .filter_map(|p| self.twist_cartesian(p.to_unit_vector()))
+                .map(to_screen)
+                .collect();
+            for pos in edge_points(&segments, &visible, &line_ends) {
+                let label = label_shapes(fonts, &line.label, pos, &visible, stroke.color);
+                // where lines meet the edge close together, label only the first
+                let bounds = label[0].visual_bounding_rect();
+                if labelled.iter().all(|other| !other.intersects(bounds)) {
+                    labelled.push(bounds);
+                    labels.extend(label);
+                }
+            }
+        }
+        // over every line
+        shapes.extend(labels);
+    }
+
+    /// everything drawn over the map: the graticule, routes, the legend, indicatrices and markers.
+    /// `rect` is the map rect and `widget` the rect that is visible.
+    fn overlay_shapes(&self, fonts: &Fonts, rect: &Rect, widget: &Rect) -> Vec<Shape> {
+        let mut shapes = vec![];
+        self.graticule_shapes(fonts, rect, widget, &mut shapes);
+        self.route_shapes(rect, &mut shapes);
+        shapes.extend(legend_shapes(fonts, widget, self.heatmap));
+
+        let stroke = Stroke::new(1.0, Color32::from_rgb(0xff, 0x80, 0x40));
+        for outline in self.tissot.outlines(&self.projection, &self.view_matrix()) {
+            let points = outline
+                .into_iter()
+                .map(|uv| rect.min + self.viewport.to_widget(uv) * rect.size());
+            shapes.push(Shape::closed_line(points.collect(), stroke));
+        }
+
+        for waypoint in self.waypoint_options.markers(&self.anchors) {
+            let Some(Vec2 { x: u, y: v }) = self.twist(waypoint) else {
+                continue;
+            };
+
+            let xy = Vec2::new(u * rect.width(), v * rect.height());
+            let stroke = Stroke::new(1.5, Color32::WHITE);
+            shapes.push(Shape::circle_stroke(rect.min + xy, 4.0, stroke));
+        }
+
+        for anchor in &self.anchors {
+            let Some(Vec2 { x: u, y: v }) = self.twist(*anchor) else {
+                continue;
+            };
+
+            let xy = Vec2::new(u * rect.width(), v * rect.height());
+            shapes.push(Shape::circle_filled(
+                rect.min + xy,
+                3.0,
+                Color32::from_rgb(0xff, 0, 0),
+            ));
+        }
+        shapes
+    }
+
+    /// render the current view with its overlays at the size in `self.export`, and save it
+    fn export_image(&self, ctx: &egui::Context) -> Result<String, String> {
+        let size = [self.export.width, self.export.height];
+        // the overlays are scaled up with the map, so the image looks like the widget
+        let pixels_per_point = if self.map_rect.width() > 0.0 {
+            size[0] as f32 / self.map_rect.width()
+        } else {
+            1.0
+        };
+        let rect = Rect::from_min_size(
+            Pos2::ZERO,
+            Vec2::new(size[0] as f32, size[1] as f32) / pixels_per_point,
+        );
+        let mut overlay_painter = OverlayPainter::new(&self.gl, ctx, pixels_per_point)?;
+        let overlays = self.overlay_shapes(overlay_painter.fonts(), &rect, &rect);
+
+        let view_matrix = self.view_matrix();
+        let slice: &[[f32; 3]; 3] = view_matrix.as_ref();
+        let matrix: Vec<f32> = slice.iter().flat_map(|x| x.iter().copied()).collect();
+        let pixels = self.world2.render_offscreen(
+            &self.gl,
+            size,
+            &matrix,
+            self.projection,
+            self.heatmap,
+            &self.layer_textures.shader_layers(&self.layers.layers),
+            &self.viewport,
+            |tile| overlay_painter.paint(&overlays, tile),
+        )?;
+
+        let path = std::path::Path::new(&self.export.path);
+        save_png(path, size, &pixels)?;
+        Ok(format!(
+            "saved {}x{} to {}",
+            size[0],
+            size[1],
+            path.display()
+        ))
     }
 }
 
@@ -55,8 +732,49 @@ impl Widget for &mut WorldMap2 {
         self.height = ui.available_height() as _;
         let response = ui.allocate_response(
             Vec2::new(self.width as f32, self.height as f32),
-            Sense::click(),
+            Sense::click_and_drag(),
         );
+        let map_rect = self.layout.map_rect(response.rect);
+
+        self.now = ui.input(|input| input.time);
+        if let Some(transition) = &self.transition {
+            if transition.finished(self.now) {
+                self.transition = None;
+            }
+        }
+        if self.transition.is_none() {
+            self.advance_tour();
+        }
+        if self.transition.is_some() || !self.tour.is_empty() {
+            ui.ctx().request_repaint();
+        }
+
+        if response.hovered() {
+            let (zoom, scroll, hover) = ui.input(|input| {
+                (
+                    input.zoom_delta(),
+                    input.scroll_delta.y,
+                    input.pointer.hover_pos(),
+                )
+            });
+            // a notch of the wheel is 50 points in egui, and zooms by about 10%
+            let factor = zoom * (scroll * 0.002).exp();
+            if let Some(pos) = hover.filter(|_| factor != 1.0) {
+                let widget = (pos - map_rect.min) / map_rect.size();
+                self.viewport.zoom_about(widget, factor);
+            }
+        }
+
+        if response.dragged_by(PointerButton::Primary) {
+            let delta = response.drag_delta() / map_rect.size();
+            if self.viewport.is_zoomed() && !ui.input(|input| input.modifiers.shift) {
+                self.viewport.pan(delta);
+            } else {
+                // content follows the pointer, so the view center moves the other way.
+                // A whole widget width is a whole turn of the visible part of the map.
+                self.set_roll(self.roll - delta.x / self.viewport.zoom * TAU);
+            }
+        }
 
         /*println!(
             "widthxheight {}x{}",
@@ -64,51 +782,67 @@ impl Widget for &mut WorldMap2 {
             response.rect.height()
         );*/
 
-        if let Some(pos) = response.hover_pos() {
-            // println!("hover {:?}", pos);
-            let Vec2 { x, y } = pos - response.rect.left_top();
-            self.last_hover = Some((x / response.rect.width(), y / response.rect.height()));
-        }
+        // println!("hover {:?}", response.hover_pos());
+        self.last_hover = response
+            .hover_pos()
+            .and_then(|pos| map_fraction(&map_rect, pos))
+            .and_then(|uv| self.untwist(uv.x, uv.y));
 
         if response.clicked() {
             if let Some(pos) = response.interact_pointer_pos() {
                 let Vec2 { x, y } = pos - response.rect.left_top();
                 println!("click {},{}", x, y);
 
-                let u = x / response.rect.width();
-                let v = y / response.rect.height();
-                let Vec2 { x: u, y: v } = transform_ll_to_ll(u, v, &self.matrix);
-
-                self.set_anchor(u, v);
+                let uv = map_fraction(&map_rect, pos);
+                if let Some(position) = uv.and_then(|uv| self.untwist(uv.x, uv.y)) {
+                    self.set_anchor(position);
+                }
 
                 // self.calculate_replacement_image(ui);
             }
         }
 
-        let rect = &response.rect;
+        self.map_rect = map_rect;
+        let rect = &map_rect;
+        // the map rect sticks out of the widget for MapLayout::Fill
+        let painter = ui.painter_at(response.rect);
+        // the bars of MapLayout::Fit
+        let [r, g, b, _] = OFF_MAP_RGBA;
+        painter.rect_filled(response.rect, 0.0, Color32::from_rgb(r, g, b));
 
         // println!("enabled? {}", ui.is_enabled());
 
         let world2 = self.world2.clone();
-        let slice: &[[f32; 3]; 3] = self.matrix.as_ref();
+        let view_matrix = self.view_matrix();
+        self.shown_matrix = view_matrix;
+        let slice: &[[f32; 3]; 3] = view_matrix.as_ref();
         let matrix: Vec<f32> = slice.iter().flat_map(|x| x.iter().copied()).collect();
+        let projection = self.projection;
+        let heatmap = self.heatmap;
+        if let Err(error) = self.layer_textures.update(&self.gl, &self.layers.layers) {
+            self.layers.error = Some(error);
+        }
+        let layers = self.layer_textures.shader_layers(&self.layers.layers);
+        let viewport = self.viewport;
         let cb = eframe::egui_glow::CallbackFn::new(move |_info, painter| {
-            world2.paint(painter.gl(), &matrix)
+            world2.paint(
+                painter.gl(),
+                &matrix,
+                projection,
+                heatmap,
+                &layers,
+                &viewport,
+            )
         });
         //println!("painting for {:?}", rect);
         let callback = PaintCallback {
             rect: *rect,
             callback: Arc::new(cb),
         };
-        ui.painter().add(Shape::Callback(callback));
+        painter.add(Shape::Callback(callback));
 
-        for anchor in &self.anchors {
-            let Vec2 { x: u, y: v } = transform_ll_to_ll(anchor.x, anchor.y, &self.matrix_inverse);
-
-            let xy = Vec2::new(u * response.rect.width(), v * response.rect.height());
-            let circle = Shape::circle_filled(rect.min + xy, 3.0, Color32::from_rgb(0xff, 0, 0));
-            ui.painter().add(circle);
-        }
+        let overlays = ui.fonts(|fonts| self.overlay_shapes(fonts, rect, &response.rect));
+        painter.extend(overlays);
 
         if false {
             let clicked: Vec<_> = [
@@ -128,3 +862,21 @@ impl Widget for &mut WorldMap2 {
         response
     }
 }
+
+/// break a projected polyline wherever it leaves the map or jumps across a seam
+fn split_polyline(points: impl Iterator<Item = Option<Vec2>>) -> Vec<Vec<Vec2>> {
+    let mut segments: Vec<Vec<Vec2>> = vec![];
+    let mut current: Vec<Vec2> = vec![];
+    for point in points {
+        match (point, current.last()) {
+            (Some(point), Some(last)) if (point - *last).length() > 0.25 => {
+                segments.push(std::mem::replace(&mut current, vec![point]));
+            }
+            (Some(point), _) => current.push(point),
+            (None, _) => segments.push(std::mem::take(&mut current)),
+        }
+    }
+    segments.push(current);
+    segments.retain(|segment| segment.len() > 1);
+    segments
+}